@@ -1,71 +1,958 @@
+use crate::compression;
+use crate::config::Config;
+use crate::deb;
 use crate::errors::*;
+use crate::extract::{Unpacked, Unpacker};
 use crate::http;
-use crate::pkg;
+use crate::offline;
+use crate::pkg::VersionDetector;
 use crate::progress::ProgressBar;
+use crate::ratelimit::RateLimiter;
+use crate::retry::{self, RetryPolicy};
+use crate::signature::{self, Signatures};
+use crate::units;
+use crate::verify;
+use crate::version::Version;
+use futures_util::future;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Read, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    time,
+};
+
+/// File name used for sources whose url does not end in one
+const DEFAULT_FILENAME: &str = "linkchats-desktop.tar.gz";
+
+/// Byte ranges smaller than this are not worth an extra connection
+const MIN_RANGE_SIZE: u64 = 1024 * 1024;
+
+/// Names of the package index in a Debian repository, in order of preference
+const PACKAGES_INDEXES: &[&str] = &["Packages.xz", "Packages.gz", "Packages"];
+
+/// Returns the path of a source that is a local file or directory, given as an absolute path or
+/// a `file://` url
+fn local_source(source: &str) -> Result<Option<PathBuf>> {
+    if source.starts_with("file:") {
+        let url = Url::parse(source).with_context(|| anyhow!("Invalid url {:?}", source))?;
+        let path = url
+            .to_file_path()
+            .map_err(|()| anyhow!("Url {:?} is not a local path", source))?;
+        Ok(Some(path))
+    } else if Path::new(source).is_absolute() {
+        Ok(Some(PathBuf::from(source)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Describes a local archive like a remote one, so an unchanged archive is not installed again
+fn local_validators(path: &Path, metadata: &std::fs::Metadata) -> http::Validators {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos().to_string());
+    http::Validators {
+        etag: Some(path.display().to_string()),
+        last_modified: modified,
+        content_length: Some(metadata.len()),
+    }
+}
+
+/// A Debian repository the package is installed from, instead of the tarball sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    /// Base url of the repository, the directory containing `dists/` and `pool/`
+    pub url: String,
+    pub suite: String,
+    pub component: String,
+    pub package: String,
+    /// Debian architecture name, e.g. `amd64`
+    pub architecture: String,
+}
+
+impl Repository {
+    fn dists_url(&self, path: &str) -> String {
+        let url = self.url.trim_end_matches('/');
+        format!("{url}/dists/{}/{path}", self.suite)
+    }
+
+    fn package_url(&self, package: &deb::Package) -> String {
+        let url = self.url.trim_end_matches('/');
+        format!("{url}/{}", package.filename.trim_start_matches('/'))
+    }
+}
+
+/// Metadata stored next to a partial download, so it can be resumed by a later launch
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialMeta {
+    url: String,
+    validators: http::Validators,
+}
+
+/// A download in progress, written to `<target>.part` with its metadata in `<target>.part.toml`
+struct PartialFile {
+    path: PathBuf,
+    meta_path: PathBuf,
+}
+
+impl PartialFile {
+    fn new(target: &Path) -> Self {
+        let mut path = target.as_os_str().to_owned();
+        path.push(".part");
+        let path = PathBuf::from(path);
+
+        let mut meta_path = path.as_os_str().to_owned();
+        meta_path.push(".toml");
+
+        Self {
+            path,
+            meta_path: PathBuf::from(meta_path),
+        }
+    }
+
+    /// Returns the number of bytes already downloaded and the validators they were downloaded with
+    async fn resume_point(&self, url: &str) -> Option<(u64, http::Validators)> {
+        let buf = fs::read(&self.meta_path).await.ok()?;
+        let meta = toml::from_slice::<PartialMeta>(&buf)
+            .inspect_err(|err| warn!("Failed to parse {:?}: {err:#}", self.meta_path))
+            .ok()?;
+        if meta.url != url {
+            debug!("Partial download is from {:?}, not resuming", meta.url);
+            return None;
+        }
+
+        let len = fs::metadata(&self.path).await.ok()?.len();
+        (len > 0).then_some((len, meta.validators))
+    }
+
+    async fn start(&self, url: &str, validators: &http::Validators) -> Result<fs::File> {
+        let meta = PartialMeta {
+            url: url.to_string(),
+            validators: validators.clone(),
+        };
+        fs::write(&self.meta_path, toml::to_string(&meta)?)
+            .await
+            .with_context(|| anyhow!("Failed to write {:?}", self.meta_path))?;
+        fs::File::create(&self.path)
+            .await
+            .with_context(|| anyhow!("Failed to create {:?}", self.path))
+    }
+
+    /// Creates the file with its final size for ranged downloads.
+    ///
+    /// No metadata is written, so an unfinished ranged download is never resumed as a stream.
+    async fn preallocate(&self, len: u64) -> Result<()> {
+        fs::remove_file(&self.meta_path).await.ok();
+        let file = fs::File::create(&self.path)
+            .await
+            .with_context(|| anyhow!("Failed to create {:?}", self.path))?;
+        file.set_len(len)
+            .await
+            .with_context(|| anyhow!("Failed to allocate {:?}", self.path))?;
+        Ok(())
+    }
+
+    async fn append(&self) -> Result<fs::File> {
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", self.path))
+    }
+
+    async fn discard(&self) {
+        fs::remove_file(&self.meta_path).await.ok();
+        fs::remove_file(&self.path).await.ok();
+    }
+
+    async fn finish(&self, target: &Path) -> Result<()> {
+        fs::rename(&self.path, target)
+            .await
+            .with_context(|| anyhow!("Failed to move download to {:?}", target))?;
+        fs::remove_file(&self.meta_path).await.ok();
+        Ok(())
+    }
+}
+
+/// An archive downloaded by [`Client::download_tar`]
+#[derive(Debug)]
+pub struct Downloaded {
+    pub path: PathBuf,
+    /// The source that served the archive
+    pub url: String,
+    pub validators: http::Validators,
+    /// Content of the `.sha256` file published next to the archive
+    pub sha256: Option<String>,
+    pub signatures: Signatures,
+    /// The archive, already unpacked while it was downloaded. `None` if it has to be verified
+    /// before it is unpacked
+    pub unpacked: Option<Unpacked>,
+}
 
 pub struct Client {
     client: http::Client,
+    sources: Vec<String>,
+    repository: Option<Repository>,
+    download_attempts: usize,
+    download_path: PathBuf,
+    /// Where archives are unpacked while they are downloaded
+    unpack_path: PathBuf,
+    /// Unpack archives while they are downloaded, only done if nothing has to be verified first
+    unpack_early: bool,
+    detector: VersionDetector,
+    /// Only this version is picked from a Debian repository
+    pin_version: Option<Version>,
+    retry_policy: RetryPolicy,
+    parallel_downloads: usize,
+    sha256_sidecar: bool,
+    fetch_minisign: bool,
+    fetch_openpgp: bool,
+    show_progress: bool,
+    /// Shared by all attempts, so retries and resumed downloads stay below the limit as well
+    rate_limiter: Option<Mutex<RateLimiter>>,
 }
 
 impl Client {
-    pub fn new(timeout: Option<u64>) -> Result<Client> {
-        let client = http::Client::new(timeout)?;
-        Ok(Client { client })
+    pub fn new(config: &Config) -> Result<Client> {
+        let client = http::Client::new(config)?;
+        if config.sources.is_empty() {
+            bail!("No update sources are configured");
+        }
+
+        Ok(Client {
+            client,
+            sources: config.sources.clone(),
+            repository: config.apt_repository.clone(),
+            download_attempts: config.download_attempts,
+            download_path: config.download_path.clone(),
+            unpack_path: config.cache_path.clone(),
+            unpack_early: config.apt_repository.is_none()
+                && !verify::is_enabled(config)
+                && !signature::is_enabled(config),
+            detector: VersionDetector::new(config),
+            pin_version: config.pin_version.clone(),
+            retry_policy: config.retry_policy.clone(),
+            parallel_downloads: config.parallel_downloads,
+            sha256_sidecar: config.sha256_sidecar,
+            fetch_minisign: !config.minisign_public_keys.is_empty(),
+            fetch_openpgp: !config.openpgp_keyrings.is_empty(),
+            show_progress: true,
+            rate_limiter: config
+                .max_download_rate
+                .map(|rate| Mutex::new(RateLimiter::new(rate))),
+        })
+    }
+
+    /// Downloads without showing a progress bar
+    pub fn without_progress(mut self) -> Self {
+        self.show_progress = false;
+        self
+    }
+
+    /// Looks up the newest version of the package in the index of a Debian repository
+    pub async fn resolve_package(&self, repository: &Repository) -> Result<deb::Package> {
+        let release_url = repository.dists_url("Release");
+        info!("Fetching repository index from {:?}", release_url);
+        let release = self
+            .client
+            .fetch(&release_url)
+            .await
+            .with_context(|| anyhow!("Failed to download {:?}", release_url))?;
+        let release = String::from_utf8(release).context("Release file is not valid utf-8")?;
+        let files = deb::parse_release(&release)?;
+
+        let index = PACKAGES_INDEXES
+            .iter()
+            .find_map(|name| {
+                let path = format!(
+                    "{}/binary-{}/{name}",
+                    repository.component, repository.architecture
+                );
+                files.iter().find(|file| file.path == path)
+            })
+            .with_context(|| {
+                anyhow!(
+                    "Repository has no packages for {}/binary-{}",
+                    repository.component,
+                    repository.architecture
+                )
+            })?;
+
+        let index_url = repository.dists_url(&index.path);
+        let buf = self
+            .client
+            .fetch(&index_url)
+            .await
+            .with_context(|| anyhow!("Failed to download {:?}", index_url))?;
+        if buf.len() as u64 != index.size {
+            bail!(
+                "Size of {:?} does not match the Release file, expected {} bytes, got {}",
+                index_url,
+                index.size,
+                buf.len()
+            );
+        }
+        let sha256 = format!("{:x}", Sha256::digest(&buf));
+        if sha256 != index.sha256 {
+            bail!(
+                "Checksum mismatch: Release file has {} for {:?}, index has {}",
+                index.sha256,
+                index_url,
+                sha256
+            );
+        }
+
+        let mut text = String::new();
+        compression::decompress(&buf[..])?
+            .1
+            .read_to_string(&mut text)
+            .with_context(|| anyhow!("Failed to decompress {:?}", index_url))?;
+        let mut packages = deb::parse_packages(&text);
+        if let Some(pinned) = &self.pin_version {
+            packages.retain(|package| {
+                Version::from_debian(&package.version).is_ok_and(|version| version == *pinned)
+            });
+        }
+
+        let package = deb::newest_package(&packages, &repository.package, &repository.architecture)
+            .cloned()
+            .with_context(|| {
+                let pinned = match &self.pin_version {
+                    Some(pinned) => format!(" in pinned version {pinned}"),
+                    None => String::new(),
+                };
+                anyhow!(
+                    "Package {:?} for {}{} was not found in the repository",
+                    repository.package,
+                    repository.architecture,
+                    pinned
+                )
+            })?;
+        info!(
+            "Newest version of {:?} in the repository is {:?}",
+            package.name, package.version
+        );
+        Ok(package)
     }
 
-    pub async fn download_tar(&self, download_attempts: usize) -> Result<Vec<u8>> {
-        let filename = pkg::DOWNLOAD_URL
-            .rsplit_once('/')
-            .map(|(_, x)| x)
-            .unwrap_or("???");
+    /// Returns the urls the archive is downloaded from, resolving the package of a Debian
+    /// repository if one is configured
+    async fn resolve_sources(&self) -> Result<(Vec<String>, Option<deb::Package>)> {
+        match &self.repository {
+            Some(repository) => {
+                let package = self.resolve_package(repository).await?;
+                Ok((vec![repository.package_url(&package)], Some(package)))
+            }
+            None => Ok((self.sources.clone(), None)),
+        }
+    }
+
+    /// Returns the urls the archive is downloaded from, in the order they are tried
+    pub async fn source_urls(&self) -> Result<Vec<String>> {
+        Ok(self.resolve_sources().await?.0)
+    }
 
-        info!("Downloading tar file for {:?}", filename);
+    /// Downloads the archive into the download directory and returns its path.
+    ///
+    /// Sources are tried in order, each one gets the full number of download attempts before
+    /// moving on to the next mirror. Interrupted downloads are kept as `.part` files and resumed
+    /// on the next call. Sources that are local directories or `file://` urls are copied from
+    /// instead, see [`Client::copy_local`].
+    ///
+    /// With the validators of the `installed` archive the download is skipped and `None` is
+    /// returned if the remote archive has not changed since.
+    pub async fn download_tar(
+        &self,
+        installed: Option<&http::Validators>,
+    ) -> Result<Option<Downloaded>> {
+        fs::create_dir_all(&self.download_path)
+            .await
+            .with_context(|| {
+                anyhow!(
+                    "Failed to create download directory {:?}",
+                    self.download_path
+                )
+            })?;
+
+        let (sources, package) = self.resolve_sources().await?;
 
         // download
-        let mut pb = ProgressBar::spawn()?;
-        let mut tar = Vec::new();
-        let mut offset = None;
+        let mut pb = if self.show_progress {
+            ProgressBar::spawn()?
+        } else {
+            ProgressBar::hidden()
+        };
 
-        let mut i: usize = 0;
-        loop {
-            // increast the counter until usize::MAX, but do not overflow
-            i = i.saturating_add(1);
-            if download_attempts > 0 && i > download_attempts {
-                // number of download attempts exceeded
-                break;
+        'sources: for url in &sources {
+            if let Some(source) = local_source(url)? {
+                match self.copy_local(&source, installed, &mut pb).await {
+                    Ok(downloaded) => {
+                        pb.close().await?;
+                        return Ok(Some(downloaded));
+                    }
+                    Err(err) if err.is::<http::NotModified>() => {
+                        pb.close().await?;
+                        return Ok(None);
+                    }
+                    Err(err) => {
+                        warn!("Failed to take the archive from {:?}: {err:#}", source);
+                        continue;
+                    }
+                }
             }
 
-            if i > 0 {
-                info!("Retrying download...");
+            let filename = url
+                .rsplit_once('/')
+                .map(|(_, x)| x)
+                .filter(|x| !x.is_empty())
+                .unwrap_or(DEFAULT_FILENAME);
+
+            info!("Downloading {:?} from {:?}", filename, url);
+
+            let target = self.download_path.join(filename);
+            let partial = PartialFile::new(&target);
+
+            // a partial download is newer than the installed archive, no need to ask
+            let resuming = partial.resume_point(url).await.is_some();
+            let known = installed.filter(|_| !resuming);
+
+            if self.parallel_downloads > 1 && !resuming {
+                match self
+                    .attempt_parallel_download(url, &partial, known, &mut pb)
+                    .await
+                {
+                    Ok(Some(validators)) => {
+                        partial.finish(&target).await?;
+                        info!("Update was downloaded from {:?}", url);
+
+                        // ranges arrive out of order, the archive is unpacked once it is complete
+                        match self
+                            .downloaded(target, url, validators, None, package.as_ref())
+                            .await
+                        {
+                            Ok(downloaded) => {
+                                pb.close().await?;
+                                return Ok(Some(downloaded));
+                            }
+                            Err(err) => {
+                                warn!("Download from {:?} is unusable: {err:#}", url);
+                                continue;
+                            }
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) if err.is::<http::NotModified>() => {
+                        pb.close().await?;
+                        return Ok(None);
+                    }
+                    Err(err) => {
+                        warn!(
+                            "Parallel download has failed, falling back to a single stream: {err:#}"
+                        );
+                        partial.discard().await;
+                    }
+                }
             }
 
-            if let Err(err) = self
-                .attempt_download(pkg::DOWNLOAD_URL, &mut tar, &mut pb, &mut offset)
+            // kept across attempts, so a resumed download continues where unpacking stopped
+            let mut unpacker = None;
+            let mut i: usize = 0;
+            loop {
+                // increast the counter until usize::MAX, but do not overflow
+                i = i.saturating_add(1);
+                if self.download_attempts > 0 && i > self.download_attempts {
+                    // number of download attempts exceeded
+                    warn!("Exceeded number of retries for {:?}", url);
+                    break;
+                }
+
+                if i > 1 {
+                    let delay = self.retry_delay(i - 2);
+                    info!("Retrying download in {:.1}s...", delay.as_secs_f64());
+                    pb.set_text(&format!(
+                        "Download failed, retrying in {}s...",
+                        delay.as_secs_f64().ceil()
+                    ))
+                    .await?;
+                    time::sleep(delay).await;
+                    pb.set_text("Downloading...").await?;
+                }
+
+                match self
+                    .attempt_download(url, &partial, known, &mut unpacker, &mut pb)
+                    .await
+                {
+                    Err(err) if err.is::<http::NotModified>() => {
+                        pb.close().await?;
+                        return Ok(None);
+                    }
+                    Err(err) if !retry::is_retryable(&err) => {
+                        warn!("Download has failed and can not be retried: {err:#}");
+                        break;
+                    }
+                    Err(err) => warn!("Download has failed: {err:#}"),
+                    Ok((validators, unpacked)) => {
+                        partial.finish(&target).await?;
+                        info!("Update was downloaded from {:?}", url);
+
+                        // like a failed download, a missing checksum moves on to the next source
+                        match self
+                            .downloaded(target, url, validators, unpacked, package.as_ref())
+                            .await
+                        {
+                            Ok(downloaded) => {
+                                pb.close().await?;
+                                return Ok(Some(downloaded));
+                            }
+                            Err(err) => {
+                                warn!("Download from {:?} is unusable: {err:#}", url);
+                                continue 'sources;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        pb.close().await?;
+        bail!("Exceeded number of retries for download from all sources");
+    }
+
+    /// Copies the archive from a local source into the download directory, unpacking it on the way
+    /// unless it has to be verified first.
+    ///
+    /// A directory, e.g. a network share, may contain several versions, the newest one is taken.
+    async fn copy_local(
+        &self,
+        source: &Path,
+        installed: Option<&http::Validators>,
+        pb: &mut ProgressBar,
+    ) -> Result<Downloaded> {
+        let metadata = fs::metadata(source)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", source))?;
+        let path = if metadata.is_dir() {
+            let (path, version) = offline::scan_dir(source, &self.detector)
+                .await?
+                .into_iter()
+                .max_by(|(_, a), (_, b)| a.cmp(b))
+                .with_context(|| anyhow!("No archives found in {:?}", source))?;
+            info!("Newest archive in {:?} is version {:?}", source, version);
+            path
+        } else {
+            source.to_path_buf()
+        };
+
+        let metadata = fs::metadata(&path)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let validators = local_validators(&path, &metadata);
+        if installed.is_some_and(|installed| installed.matches(&validators)) {
+            return Err(Error::new(http::NotModified));
+        }
+
+        let filename = path
+            .file_name()
+            .with_context(|| anyhow!("Archive {:?} has no file name", path))?;
+        let target = self.download_path.join(filename);
+        let partial = PartialFile::new(&target);
+        info!("Copying {:?} to {:?}", path, target);
+
+        let result = async {
+            let mut src = fs::File::open(&path)
                 .await
-            {
-                warn!("Download has failed: {err:#}");
+                .with_context(|| anyhow!("Failed to open {:?}", path))?;
+            let mut file = fs::File::create(&partial.path)
+                .await
+                .with_context(|| anyhow!("Failed to create {:?}", partial.path))?;
+            let mut unpacker = if self.unpack_early {
+                Some(Unpacker::new(&self.unpack_path, &self.detector)?)
             } else {
-                pb.close().await?;
+                None
+            };
+            let mut copied = 0;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = src
+                    .read(&mut buf)
+                    .await
+                    .with_context(|| anyhow!("Failed to read {:?}", path))?;
+                if n == 0 {
+                    break;
+                }
+                file.write_all(&buf[..n])
+                    .await
+                    .context("Failed to write archive")?;
+                if let Some(unpacker) = &mut unpacker {
+                    unpacker
+                        .feed(&buf[..n])
+                        .await
+                        .context("Failed to unpack archive")?;
+                }
+                copied += n as u64;
 
-                return Ok(tar);
+                let progress = (copied as f64 / metadata.len() as f64 * 100.0) as u64;
+                pb.update(progress).await?;
+            }
+            file.flush().await.context("Failed to write archive")?;
+            match unpacker {
+                Some(unpacker) => unpacker
+                    .finish(filename.to_str())
+                    .await
+                    .map(Some)
+                    .context("Failed to unpack archive"),
+                None => Ok(None),
             }
         }
+        .await;
+        let unpacked = match result {
+            Ok(unpacked) => unpacked,
+            Err(err) => {
+                partial.discard().await;
+                return Err(err);
+            }
+        };
+        partial.finish(&target).await?;
 
-        pb.close().await?;
-        bail!("Exceeded number of retries for download");
+        let sha256 = if self.sha256_sidecar {
+            verify::local_sidecar(&path).await?
+        } else {
+            None
+        };
+        let signatures =
+            signature::read_local(&path, self.fetch_minisign, self.fetch_openpgp).await?;
+
+        Ok(Downloaded {
+            path: target,
+            url: path.display().to_string(),
+            validators,
+            sha256,
+            signatures,
+            unpacked,
+        })
     }
 
-    async fn attempt_download(
+    async fn downloaded(
+        &self,
+        path: PathBuf,
+        url: &str,
+        validators: http::Validators,
+        unpacked: Option<Unpacked>,
+        package: Option<&deb::Package>,
+    ) -> Result<Downloaded> {
+        if let Some(package) = package {
+            let size = fs::metadata(&path)
+                .await
+                .with_context(|| anyhow!("Failed to open {:?}", path))?
+                .len();
+            let mismatch = if size != package.size {
+                Some(format!("expected {} bytes, got {}", package.size, size))
+            } else {
+                let actual = match &unpacked {
+                    Some(unpacked) => unpacked.sha256.clone(),
+                    None => verify::sha256_file(&path).await?,
+                };
+                (actual != package.sha256).then(|| {
+                    format!(
+                        "checksum mismatch: expected sha256 {}, package has {}",
+                        package.sha256, actual
+                    )
+                })
+            };
+            if let Some(mismatch) = mismatch {
+                fs::remove_file(&path).await.ok();
+                bail!("Package does not match the repository index, {mismatch}");
+            }
+            info!("Package matches the size and sha256 hash from the repository index");
+        }
+
+        let sha256 = if self.sha256_sidecar {
+            let sidecar = format!("{url}.sha256");
+            let checksum = async {
+                let buf =
+                    self.client.fetch(&sidecar).await.with_context(|| {
+                        anyhow!("Failed to download checksum from {:?}", sidecar)
+                    })?;
+                String::from_utf8(buf).context("Checksum file is not valid utf-8")
+            }
+            .await;
+            match checksum {
+                Ok(checksum) => Some(checksum),
+                Err(err) => {
+                    fs::remove_file(&path).await.ok();
+                    return Err(err);
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut signatures = Signatures::default();
+        if self.fetch_minisign {
+            signatures.minisign = self
+                .fetch_signature(url, signature::MINISIGN_EXTENSION)
+                .await
+                .map(|buf| String::from_utf8_lossy(&buf).into_owned());
+        }
+        if self.fetch_openpgp {
+            signatures.openpgp = self
+                .fetch_signature(url, signature::OPENPGP_EXTENSION)
+                .await;
+        }
+
+        Ok(Downloaded {
+            path,
+            url: url.to_string(),
+            validators,
+            sha256,
+            signatures,
+            unpacked,
+        })
+    }
+
+    /// Downloads the detached signature published as `<url>.<extension>`.
+    ///
+    /// A missing signature is not an error here, the signature policy decides what happens then.
+    async fn fetch_signature(&self, url: &str, extension: &str) -> Option<Vec<u8>> {
+        let url = format!("{url}.{extension}");
+        match self.client.fetch(&url).await {
+            Ok(buf) => Some(buf),
+            Err(err) => {
+                warn!("Failed to download signature from {:?}: {err:#}", url);
+                None
+            }
+        }
+    }
+
+    fn retry_delay(&self, retry: usize) -> Duration {
+        self.retry_policy
+            .delay(u32::try_from(retry).unwrap_or(u32::MAX))
+    }
+
+    /// Waits as long as needed to stay below the configured download rate
+    async fn throttle(&self, bytes: usize) {
+        if let Some(limiter) = &self.rate_limiter {
+            let delay = limiter.lock().unwrap().consume(bytes as u64);
+            if !delay.is_zero() {
+                time::sleep(delay).await;
+            }
+        }
+    }
+
+    /// Downloads the archive as several byte ranges at once.
+    ///
+    /// Returns `None` if the server does not support ranged requests or the archive is too small
+    /// to be split, the caller is expected to fall back to a single stream.
+    async fn attempt_parallel_download(
         &self,
         url: &str,
-        tar: &mut Vec<u8>,
+        partial: &PartialFile,
+        known: Option<&http::Validators>,
         pb: &mut ProgressBar,
-        offset: &mut Option<u64>,
+    ) -> Result<Option<http::Validators>> {
+        let Some((total, validators)) = self.client.probe_ranges(url, known).await? else {
+            info!("Server does not support ranged requests, downloading as a single stream");
+            return Ok(None);
+        };
+        if known.is_some_and(|known| known.matches(&validators)) {
+            debug!("Server ignored the conditional request, but the archive is unchanged");
+            return Err(Error::new(http::NotModified));
+        }
+
+        let count = total
+            .div_ceil(MIN_RANGE_SIZE)
+            .min(self.parallel_downloads as u64);
+        if count < 2 {
+            debug!("Archive is too small to be split, downloading as a single stream");
+            return Ok(None);
+        }
+
+        info!(
+            "Downloading {} in {} parallel ranges",
+            units::format_size(total),
+            count
+        );
+        partial.preallocate(total).await?;
+
+        let range_size = total.div_ceil(count);
+        let progress = AtomicU64::new(0);
+        let ranges = (0..count)
+            .map(|n| (n * range_size, ((n + 1) * range_size).min(total) - 1))
+            .map(|(start, end)| {
+                self.download_range(url, partial, start, end, &validators, &progress)
+            });
+
+        let downloads = future::try_join_all(ranges);
+        tokio::pin!(downloads);
+        let mut interval = time::interval(Duration::from_millis(500));
+        loop {
+            tokio::select! {
+                result = &mut downloads => {
+                    result?;
+                    break;
+                }
+                _ = interval.tick() => {
+                    let done = progress.load(Ordering::Relaxed);
+                    let percent = (done as f64 / total as f64 * 100.0) as u64;
+                    pb.update(percent).await?;
+                    debug!("Download progress: {}%, {}/{}", percent, done, total);
+                }
+            }
+        }
+        pb.update(100).await?;
+
+        Ok(Some(validators.clone()))
+    }
+
+    /// Downloads `start..=end` into its place in the partial file, retrying on its own
+    async fn download_range(
+        &self,
+        url: &str,
+        partial: &PartialFile,
+        start: u64,
+        end: u64,
+        validators: &http::Validators,
+        progress: &AtomicU64,
     ) -> Result<()> {
-        let mut dl = self.client.fetch_stream(url, *offset).await?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(&partial.path)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", partial.path))?;
+
+        let mut offset = start;
+        let mut i: usize = 0;
+        loop {
+            i = i.saturating_add(1);
+            if i > 1 {
+                let delay = self.retry_delay(i - 2);
+                debug!(
+                    "Retrying bytes {}-{} in {:.1}s...",
+                    offset,
+                    end,
+                    delay.as_secs_f64()
+                );
+                time::sleep(delay).await;
+            }
+
+            let result = async {
+                let mut dl = self.client.fetch_range(url, offset, end).await?;
+                if !validators.unchanged(&dl.validators) {
+                    bail!("Remote archive has changed during the download");
+                }
+
+                file.seek(SeekFrom::Start(offset)).await?;
+                while let Some(chunk) = dl.chunk().await? {
+                    if offset + chunk.len() as u64 > end + 1 {
+                        bail!("Download server sent more data than requested");
+                    }
+                    file.write_all(&chunk)
+                        .await
+                        .context("Failed to write downloaded data")?;
+                    offset += chunk.len() as u64;
+                    progress.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                    self.throttle(chunk.len()).await;
+                }
+                file.flush()
+                    .await
+                    .context("Failed to write downloaded data")?;
+
+                if offset != end + 1 {
+                    bail!("Download of bytes {}-{} ended early", start, end);
+                }
+                Ok(())
+            }
+            .await;
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if !retry::is_retryable(&err) => return Err(err),
+                Err(err) if self.download_attempts > 0 && i >= self.download_attempts => {
+                    return Err(err.context("Exceeded number of retries for a download range"));
+                }
+                Err(err) => warn!("Download of bytes {}-{} has failed: {err:#}", offset, end),
+            }
+        }
+    }
+
+    async fn attempt_download(
+        &self,
+        url: &str,
+        partial: &PartialFile,
+        known: Option<&http::Validators>,
+        unpacker: &mut Option<Unpacker>,
+        pb: &mut ProgressBar,
+    ) -> Result<(http::Validators, Option<Unpacked>)> {
+        let resume = partial.resume_point(url).await;
+        if let Some((offset, _)) = &resume {
+            info!("Resuming download at {} bytes", offset);
+        }
+
+        let mut dl = self
+            .client
+            .fetch_stream(url, resume.as_ref().map(|(offset, _)| *offset), known)
+            .await?;
+        if known.is_some_and(|known| known.matches(&dl.validators)) {
+            debug!("Server ignored the conditional request, but the archive is unchanged");
+            return Err(Error::new(http::NotModified));
+        }
+
+        let started = Instant::now();
+        let offset = dl.progress;
+        let mut file = match resume {
+            Some((offset, validators)) if dl.progress == offset => {
+                if !validators.unchanged(&dl.validators) {
+                    partial.discard().await;
+                    bail!("Remote archive has changed since the download was started");
+                }
+                partial.append().await?
+            }
+            Some(_) => {
+                // the server sent the full body, the partial data is worthless now
+                pb.update(0).await?;
+                partial.start(url, &dl.validators).await?
+            }
+            None => partial.start(url, &dl.validators).await?,
+        };
+
+        // the unpacker has to see every byte of the archive, catch up with the partial file
+        if self.unpack_early
+            && unpacker
+                .as_ref()
+                .is_none_or(|unpacker| unpacker.len() != offset)
+        {
+            let mut fresh = Unpacker::new(&self.unpack_path, &self.detector)?;
+            if offset > 0 {
+                fresh.feed_file(&partial.path, offset).await?;
+            }
+            *unpacker = Some(fresh);
+        }
+
         while let Some(chunk) = dl.chunk().await? {
-            tar.extend(&chunk);
-            *offset = Some(dl.progress);
+            file.write_all(&chunk)
+                .await
+                .context("Failed to write downloaded data")?;
+            if self.unpack_early
+                && let Err(err) = Self::unpack(unpacker, &chunk).await
+            {
+                partial.discard().await;
+                return Err(err);
+            }
+
+            self.throttle(chunk.len()).await;
 
             let progress = (dl.progress as f64 / dl.total as f64 * 100.0) as u64;
             pb.update(progress).await?;
@@ -74,6 +961,565 @@ impl Client {
                 progress, dl.progress, dl.total
             );
         }
+        file.flush()
+            .await
+            .context("Failed to write downloaded data")?;
+
+        let elapsed = started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            debug!(
+                "Downloaded {} at {}/s",
+                units::format_size(dl.progress - offset),
+                units::format_size(((dl.progress - offset) as f64 / elapsed) as u64)
+            );
+        }
+
+        if !self.unpack_early {
+            return Ok((dl.validators, None));
+        }
+        let unpacked = match unpacker.take() {
+            Some(unpacker) => unpacker.finish(dl.file_name().as_deref()).await,
+            None => Err(anyhow!("Unpacker has already finished")),
+        };
+        match unpacked {
+            Ok(unpacked) => Ok((dl.validators, Some(unpacked))),
+            Err(err) => {
+                // the data is broken, start over instead of resuming
+                partial.discard().await;
+                Err(err.context("Failed to unpack archive"))
+            }
+        }
+    }
+
+    async fn unpack(unpacker: &mut Option<Unpacker>, chunk: &[u8]) -> Result<()> {
+        let Some(active) = unpacker else {
+            bail!("Unpacker has already finished");
+        };
+        if let Err(err) = active.feed(chunk).await {
+            // the data is broken, start over instead of resuming
+            *unpacker = None;
+            return Err(err.context("Failed to unpack archive"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_in;
+    use crate::http::tests::{request_header, request_path, response, serve};
+    use std::{
+        collections::HashMap,
+        io::Write,
+        os::unix::fs::MetadataExt,
+        sync::{Arc, atomic::AtomicBool},
+    };
+
+    /// A package as listed in the index, the size and hash may differ from the `.deb`
+    struct Listed {
+        version: &'static str,
+        deb: Vec<u8>,
+        size: u64,
+        sha256: String,
+    }
+
+    impl Listed {
+        fn new(version: &'static str, deb: &[u8]) -> Self {
+            Listed {
+                version,
+                deb: deb.to_vec(),
+                size: deb.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(deb)),
+            }
+        }
+    }
+
+    /// Adds the files of a Debian repository at `/<name>` to `files`
+    fn add_repository(
+        files: &mut HashMap<String, Vec<u8>>,
+        name: &str,
+        packages: &[Listed],
+    ) -> Result<()> {
+        let mut index = String::new();
+        for package in packages {
+            let filename = format!("pool/linkchats-desktop_{}_amd64.deb", package.version);
+            index += &format!(
+                "Package: linkchats-desktop\nVersion: {}\nArchitecture: amd64\nFilename: {filename}\nSize: {}\nSHA256: {}\n\n",
+                package.version, package.size, package.sha256
+            );
+            files.insert(format!("/{name}/{filename}"), package.deb.clone());
+        }
+
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+        encoder.write_all(index.as_bytes())?;
+        let index = encoder.finish().into_result()?;
+        let release = format!(
+            "Suite: stable\nSHA256:\n {:x} {} main/binary-amd64/Packages.gz\n",
+            Sha256::digest(&index),
+            index.len()
+        );
+        files.insert(
+            format!("/{name}/dists/stable/Release"),
+            release.into_bytes(),
+        );
+        files.insert(
+            format!("/{name}/dists/stable/main/binary-amd64/Packages.gz"),
+            index,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_source() -> Result<()> {
+        assert_eq!(
+            local_source("/mnt/share/linkchats")?,
+            Some(PathBuf::from("/mnt/share/linkchats"))
+        );
+        assert_eq!(
+            local_source("file:///mnt/share/linkchats%20desktop/")?,
+            Some(PathBuf::from("/mnt/share/linkchats desktop/"))
+        );
+        assert_eq!(
+            local_source("https://apps.webinar.ru/weteams/linkchats-desktop.tar.gz")?,
+            None
+        );
+        assert!(local_source("file://server/share").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_validators() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("linkchats-desktop-1.2.3.tar.gz");
+        std::fs::write(&path, b"archive")?;
+
+        let validators = local_validators(&path, &std::fs::metadata(&path)?);
+        assert_eq!(validators.content_length, Some(7));
+        assert!(validators.matches(&local_validators(&path, &std::fs::metadata(&path)?)));
+
+        let other = dir.path().join("linkchats-desktop-1.2.4.tar.gz");
+        std::fs::copy(&path, &other)?;
+        assert!(!validators.matches(&local_validators(&other, &std::fs::metadata(&other)?)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_from_repository() -> Result<()> {
+        let old = deb::tests::package("1.9.0-1", &[("./opt/linkchats/mtslink.bin", b"old")])?;
+        let new = deb::tests::package("1.10.0-1", &[("./opt/linkchats/mtslink.bin", b"new")])?;
+
+        let mut files = HashMap::new();
+        add_repository(
+            &mut files,
+            "good",
+            &[Listed::new("1.10.0-1", &new), Listed::new("1.9.0-1", &old)],
+        )?;
+        add_repository(
+            &mut files,
+            "size",
+            &[Listed {
+                size: new.len() as u64 + 1,
+                ..Listed::new("1.10.0-1", &new)
+            }],
+        )?;
+        add_repository(
+            &mut files,
+            "sha256",
+            &[Listed {
+                sha256: "0".repeat(64),
+                ..Listed::new("1.10.0-1", &new)
+            }],
+        )?;
+        let url = serve(move |request| match files.get(request_path(request)) {
+            Some(body) => response("200 OK", &[], body),
+            None => response("404 Not Found", &[], b""),
+        })
+        .await?;
+
+        let dir = tempfile::tempdir()?;
+        let client = |name: &str| -> Result<Client> {
+            let mut config = crate::config::tests::config()?;
+            config.apt_repository = Some(Repository {
+                url: format!("{url}/{name}"),
+                suite: "stable".to_string(),
+                component: "main".to_string(),
+                package: crate::pkg::DEB_PACKAGE.to_string(),
+                architecture: "amd64".to_string(),
+            });
+            config.download_path = dir.path().join(name);
+            config.cache_path = dir.path().to_path_buf();
+            config.download_attempts = 1;
+            Ok(Client::new(&config)?.without_progress())
+        };
+
+        let downloaded = client("good")?.download_tar(None).await?.unwrap();
+        assert!(
+            downloaded
+                .url
+                .ends_with("/pool/linkchats-desktop_1.10.0-1_amd64.deb")
+        );
+        assert_eq!(std::fs::read(&downloaded.path)?, new);
+
+        for name in ["size", "sha256"] {
+            assert!(client(name)?.download_tar(None).await.is_err());
+            // the rejected package is not kept around
+            assert_eq!(std::fs::read_dir(dir.path().join(name))?.count(), 0);
+        }
+        Ok(())
+    }
+
+    /// A client downloading from `sources`, the progress bar is hidden
+    fn client(dir: &Path, sources: &[String]) -> Result<Client> {
+        let mut config = config_in(dir)?;
+        config.sources = sources.to_vec();
+        config.download_attempts = 2;
+        config.retry_policy.initial_delay = Duration::ZERO;
+        config.retry_policy.max_delay = Duration::ZERO;
+        Ok(Client::new(&config)?.without_progress())
+    }
+
+    #[tokio::test]
+    async fn test_download_not_modified() -> Result<()> {
+        const LAST_MODIFIED: &str = "Mon, 01 Jan 2024 00:00:00 GMT";
+        let archive = crate::pkg::tests::tar(&[("linkchats-desktop-1.2.3/mtslink.bin", b"")])?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            let headers = [("ETag", "\"v1\""), ("Last-Modified", LAST_MODIFIED)];
+            match request_path(request) {
+                "/conditional.tar"
+                    if request_header(request, "if-none-match") == Some("\"v1\"") =>
+                {
+                    response("304 Not Modified", &headers, b"")
+                }
+                // answers every request in full, even one for a range
+                _ => response("200 OK", &headers, &body),
+            }
+        })
+        .await?;
+        let dir = tempfile::tempdir()?;
+        let known = http::Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some(LAST_MODIFIED.to_string()),
+            content_length: Some(archive.len() as u64),
+        };
+
+        let client = client(dir.path(), &[format!("{url}/conditional.tar")])?;
+        assert!(client.download_tar(Some(&known)).await?.is_none());
+        for request in requests.lock().unwrap().drain(..) {
+            assert_eq!(request_header(&request, "if-none-match"), Some("\"v1\""));
+            assert_eq!(
+                request_header(&request, "if-modified-since"),
+                Some(LAST_MODIFIED)
+            );
+        }
+
+        // the server ignores the condition, but the archive is the same
+        let client = self::client(dir.path(), &[format!("{url}/unconditional.tar")])?;
+        assert!(client.download_tar(Some(&known)).await?.is_none());
+        assert!(!requests.lock().unwrap().is_empty());
+
+        // without validators everything is downloaded
+        let downloaded = client.download_tar(None).await?.unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+        Ok(())
+    }
+
+    /// A tarball large enough to be split into several ranges
+    fn large_archive() -> Result<Vec<u8>> {
+        let data = (0..3 * MIN_RANGE_SIZE)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        crate::pkg::tests::tar(&[("linkchats-desktop-1.2.3/mtslink.bin", &data)])
+    }
+
+    /// Answers a request for `body`, with a partial response if a range was requested
+    fn ranged(request: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let range = request_header(request, "range")
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.split_once('-'));
+        let Some((start, end)) = range else {
+            return response("200 OK", headers, body);
+        };
+        let start = start.parse::<usize>().unwrap();
+        let end = end
+            .parse::<usize>()
+            .map_or(body.len() - 1, |end| end.min(body.len() - 1));
+        let content_range = format!("bytes {start}-{end}/{}", body.len());
+        let mut headers = headers.to_vec();
+        headers.push(("Content-Range", &content_range));
+        response("206 Partial Content", &headers, &body[start..=end])
+    }
+
+    /// Ranges requested from the server, the probe for range support included
+    fn requested_ranges(requests: &Mutex<Vec<String>>) -> Vec<String> {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|request| request_header(request, "range").map(String::from))
+            .collect()
+    }
+
+    fn parallel_client(dir: &Path, url: &str) -> Result<Client> {
+        let mut client = client(dir, &[format!("{url}/linkchats-desktop.tar")])?;
+        client.parallel_downloads = 4;
+        Ok(client)
+    }
+
+    #[tokio::test]
+    async fn test_parallel_download() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            ranged(request, &[("ETag", "\"v1\"")], &body)
+        })
+        .await?;
+        let dir = tempfile::tempdir()?;
+
+        let downloaded = parallel_client(dir.path(), &url)?
+            .download_tar(None)
+            .await?
+            .unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+        assert_eq!(downloaded.validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            downloaded.validators.content_length,
+            Some(archive.len() as u64)
+        );
+
+        // the probe and one request per range, nothing was streamed
+        let ranges = requested_ranges(&requests);
+        assert_eq!(ranges.len(), 5);
+        assert_eq!(ranges[0], "bytes=0-0");
+        assert_eq!(requests.lock().unwrap().len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_download_retries_range() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let failed = AtomicBool::new(false);
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            let last = request_header(request, "range")
+                .is_some_and(|range| range.ends_with(&format!("-{}", body.len() - 1)));
+            if last && !failed.swap(true, Ordering::Relaxed) {
+                return response("503 Service Unavailable", &[], b"");
+            }
+            ranged(request, &[("ETag", "\"v1\"")], &body)
+        })
+        .await?;
+        let dir = tempfile::tempdir()?;
+
+        let downloaded = parallel_client(dir.path(), &url)?
+            .download_tar(None)
+            .await?
+            .unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+
+        // only the failed range was requested again
+        let ranges = requested_ranges(&requests);
+        assert_eq!(ranges.len(), 6);
+        let last = ranges.last().unwrap();
+        assert_eq!(ranges.iter().filter(|range| *range == last).count(), 2);
+        assert_eq!(requests.lock().unwrap().len(), 6);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_download_fallback() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            let probe = request_header(request, "range") == Some("bytes=0-0");
+            match request_path(request) {
+                // ranges are only supported for the probe
+                "/ignored/linkchats-desktop.tar" if !probe => {
+                    response("200 OK", &[("ETag", "\"v1\"")], &body)
+                }
+                // the archive is replaced after the probe
+                "/changed/linkchats-desktop.tar" if !probe => {
+                    ranged(request, &[("ETag", "\"v2\"")], &body)
+                }
+                _ => ranged(request, &[("ETag", "\"v1\"")], &body),
+            }
+        })
+        .await?;
+
+        for (name, etag) in [("ignored", "\"v1\""), ("changed", "\"v2\"")] {
+            let dir = tempfile::tempdir()?;
+            requests.lock().unwrap().clear();
+
+            let downloaded = parallel_client(dir.path(), &format!("{url}/{name}"))?
+                .download_tar(None)
+                .await?
+                .unwrap();
+            assert_eq!(std::fs::read(&downloaded.path)?, archive);
+            assert_eq!(downloaded.validators.etag.as_deref(), Some(etag));
+
+            // ranges were requested first, then the archive was downloaded as a single stream
+            let requests = requests.lock().unwrap();
+            assert!(requests.iter().any(|request| {
+                request_header(request, "range").is_some_and(|range| range != "bytes=0-0")
+            }));
+            let last = requests.last().unwrap();
+            assert_eq!(request_header(last, "range"), None);
+            assert!(requests.len() > 2);
+        }
+        Ok(())
+    }
+
+    /// Leaves the first `len` bytes of `data` behind as an interrupted download of `url`
+    async fn interrupted(
+        client: &Client,
+        url: &str,
+        validators: &http::Validators,
+        data: &[u8],
+    ) -> Result<PartialFile> {
+        fs::create_dir_all(&client.download_path).await?;
+        let partial = PartialFile::new(&client.download_path.join("linkchats-desktop.tar"));
+        let mut file = partial.start(url, validators).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(partial)
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            ranged(request, &[("ETag", "\"v1\"")], &body)
+        })
+        .await?;
+        let url = format!("{url}/linkchats-desktop.tar");
+        let dir = tempfile::tempdir()?;
+        let client = client(dir.path(), std::slice::from_ref(&url))?;
+        let validators = http::Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            content_length: Some(archive.len() as u64),
+        };
+        let partial = interrupted(&client, &url, &validators, &archive[..1000]).await?;
+
+        let downloaded = client.download_tar(None).await?.unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+        assert!(downloaded.unpacked.is_some());
+        assert!(!partial.path.exists());
+        assert!(!partial.meta_path.exists());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(request_header(&requests[0], "range"), Some("bytes=1000-"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_changed() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            ranged(request, &[("ETag", "\"v2\"")], &body)
+        })
+        .await?;
+        let url = format!("{url}/linkchats-desktop.tar");
+        let dir = tempfile::tempdir()?;
+        let client = client(dir.path(), std::slice::from_ref(&url))?;
+        let validators = http::Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            content_length: Some(archive.len() as u64),
+        };
+        interrupted(&client, &url, &validators, b"stale data of the old archive").await?;
+
+        let downloaded = client.download_tar(None).await?.unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+        assert_eq!(downloaded.validators.etag.as_deref(), Some("\"v2\""));
+
+        // the resume was refused, the archive was downloaded again from the start
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(request_header(&requests[0], "range"), Some("bytes=29-"));
+        assert_eq!(request_header(&requests[1], "range"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_restart() -> Result<()> {
+        let archive = large_archive()?;
+        let body = archive.clone();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        // the server does not support ranges
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            response("200 OK", &[("ETag", "\"v1\"")], &body)
+        })
+        .await?;
+        let url = format!("{url}/linkchats-desktop.tar");
+        let dir = tempfile::tempdir()?;
+        let client = client(dir.path(), std::slice::from_ref(&url))?;
+        let validators = http::Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            content_length: Some(archive.len() as u64),
+        };
+        interrupted(&client, &url, &validators, &[0xff; 1000]).await?;
+
+        // the full body replaces the partial data instead of being appended to it
+        let downloaded = client.download_tar(None).await?.unwrap();
+        assert_eq!(std::fs::read(&downloaded.path)?, archive);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(request_header(&requests[0], "range"), Some("bytes=1000-"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_finish() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("linkchats-desktop.tar");
+        std::fs::write(&target, b"previous download")?;
+
+        let partial = PartialFile::new(&target);
+        assert_eq!(partial.path, dir.path().join("linkchats-desktop.tar.part"));
+        assert_eq!(
+            partial.meta_path,
+            dir.path().join("linkchats-desktop.tar.part.toml")
+        );
+        let mut file = partial
+            .start("https://example.com/", &http::Validators::default())
+            .await?;
+        file.write_all(b"new download").await?;
+        file.flush().await?;
+        let inode = std::fs::metadata(&partial.path)?.ino();
+
+        // the partial file is moved in place, replacing the previous download
+        partial.finish(&target).await?;
+        assert_eq!(std::fs::read(&target)?, b"new download");
+        assert_eq!(std::fs::metadata(&target)?.ino(), inode);
+        assert!(!partial.path.exists());
+        assert!(!partial.meta_path.exists());
         Ok(())
     }
 }
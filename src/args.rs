@@ -3,9 +3,14 @@ use std::path::PathBuf;
 #[derive(Debug, clap::Parser)]
 #[command(version)]
 pub struct Args {
-    /// Use a local .tar file instead of downloading one
+    #[command(subcommand)]
+    pub command: Option<SubCommand>,
+    /// Use a local .tar, .tar.gz, .tar.xz, .tar.zst or .deb file instead of downloading one
     #[arg(long)]
     pub tar: Option<PathBuf>,
+    /// Only install the archive if it has this sha256 hash
+    #[arg(long, value_name = "HASH")]
+    pub sha256: Option<String>,
     /// Install into specific directory
     #[arg(long)]
     pub install_dir: Option<PathBuf>,
@@ -18,9 +23,45 @@ pub struct Args {
     /// How often do you need to check for updates
     #[arg(long)]
     pub check_update_interval: Option<usize>,
-    /// Check for the latest .tar.gz and print its url
+    /// Download from this url, local directory or file:// url instead of the configured sources
+    /// (can be used multiple times)
+    #[arg(long = "source", value_name = "URL")]
+    pub sources: Vec<String>,
+    /// Print the urls of the archive sources in the order they are tried
     #[arg(long)]
     pub print_tar_url: bool,
+    /// Limit the download speed, e.g. 2MiB/s or 500KB/s
+    #[arg(long, value_name = "RATE")]
+    pub max_download_rate: Option<String>,
+    /// Download the archive as this many byte ranges at once, if the server supports it
+    #[arg(long, value_name = "N")]
+    pub parallel_downloads: Option<usize>,
+    /// Proxy to use for downloads, e.g. http://proxy:3128 or socks5://proxy:1080
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+    /// Credentials for the proxy as username:password
+    #[arg(long, value_name = "USER:PASSWORD")]
+    pub proxy_auth: Option<String>,
+    /// Connect to this host directly instead of using the proxy (can be used multiple times)
+    #[arg(long, value_name = "HOST")]
+    pub no_proxy: Vec<String>,
+    /// Never use the network, install newer archives from the cache or archive directory only
+    #[arg(long)]
+    pub offline: bool,
+    /// Launch the installed version right away and download updates in the background,
+    /// they are applied when the app exits or on the next launch
+    #[arg(long)]
+    pub background_update: bool,
+    /// Install the update even if it is older than the installed version
+    #[arg(long)]
+    pub allow_downgrade: bool,
+    /// Refuse to install any version but this one
+    #[arg(long, value_name = "VERSION")]
+    pub pin_version: Option<String>,
+    /// Keep the installed version and skip update checks. If nothing is installed yet, the
+    /// latest version is installed once
+    #[arg(long)]
+    pub hold: bool,
     /// Run the install/update code but don't actually run the final binary
     #[arg(long)]
     pub no_exec: bool,
@@ -31,3 +72,17 @@ pub struct Args {
     #[arg(long)]
     pub download_attempts: Option<usize>,
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, clap::Subcommand)]
+pub enum SubCommand {
+    /// Check for updates and install them without launching the app
+    ///
+    /// While the app is running the update is applied once it exits.
+    Update,
+    /// Check for updates with a systemd user timer, every check_update_interval seconds
+    InstallTimer,
+    /// Remove the systemd user timer again
+    UninstallTimer,
+    /// Show the installed version and the metadata of the installed app
+    Status,
+}
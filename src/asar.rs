@@ -0,0 +1,306 @@
+use crate::errors::*;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+use tokio::task;
+
+/// Path of the archive with the app sources, relative to the installation
+pub const APP_ASAR: &str = "resources/app.asar";
+
+/// Headers larger than this are not from an app we know
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+/// Files read from the archive are kept in memory, they are only expected to be small
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// An entry of the json header of an asar archive
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Node {
+    Directory {
+        files: HashMap<String, Node>,
+    },
+    File {
+        /// Offset from the end of the header, as a string since it may exceed 2^53
+        offset: Option<String>,
+        size: u64,
+        /// Stored in `app.asar.unpacked` instead of the archive itself
+        #[serde(default)]
+        unpacked: bool,
+    },
+    Link {
+        #[allow(dead_code)]
+        link: String,
+    },
+}
+
+/// The `package.json` of an Electron app
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageJson {
+    pub name: Option<String>,
+    pub version: String,
+    pub product_name: Option<String>,
+}
+
+/// Parsed header of an asar archive
+struct Header {
+    root: Node,
+    /// Position of the file data, file offsets are relative to it
+    data_start: u64,
+    /// Bytes read to parse the header
+    consumed: u64,
+}
+
+/// Reads the header from the start of an asar archive
+fn read_header<R: Read>(mut reader: R) -> Result<Header> {
+    // the header is a pickle: size of the size field, header size, payload size, json size, json
+    let mut prefix = [0; 16];
+    reader
+        .read_exact(&mut prefix)
+        .context("Failed to read asar header")?;
+    let field = |i: usize| u64::from(u32::from_le_bytes(prefix[i..i + 4].try_into().unwrap()));
+    let (header_size, json_size) = (field(4), field(12));
+    if field(0) != 4 || json_size + 8 > header_size || header_size > MAX_HEADER_SIZE {
+        bail!("Invalid asar header");
+    }
+
+    let mut json = Vec::new();
+    (&mut reader)
+        .take(json_size)
+        .read_to_end(&mut json)
+        .context("Failed to read asar header")?;
+    if json.len() as u64 != json_size {
+        bail!("Asar header ended unexpectedly");
+    }
+    let root = serde_json::from_slice(&json).context("Failed to parse asar header")?;
+
+    Ok(Header {
+        root,
+        data_start: 8 + header_size,
+        consumed: 16 + json_size,
+    })
+}
+
+/// Looks up a file in the header, returns its offset and size
+fn find_file(header: &Header, path: &str) -> Result<(u64, u64)> {
+    let mut node = &header.root;
+    for name in path.split('/') {
+        let Node::Directory { files } = node else {
+            bail!("{:?} was not found in asar archive", path);
+        };
+        node = files
+            .get(name)
+            .with_context(|| anyhow!("{:?} was not found in asar archive", path))?;
+    }
+    let Node::File {
+        offset,
+        size,
+        unpacked,
+    } = node
+    else {
+        bail!("{:?} is not a file in asar archive", path);
+    };
+    if *unpacked {
+        bail!("{:?} is stored outside of the asar archive", path);
+    }
+    if *size > MAX_FILE_SIZE {
+        bail!("{:?} in asar archive is too large", path);
+    }
+    let offset = offset
+        .as_deref()
+        .context("File in asar archive has no offset")?
+        .parse()
+        .context("Invalid file offset in asar archive")?;
+    Ok((offset, *size))
+}
+
+/// Reads `size` bytes, failing if the archive ends before
+fn read_data<R: Read>(reader: R, size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader
+        .take(size)
+        .read_to_end(&mut data)
+        .context("Failed to read asar archive")?;
+    if data.len() as u64 != size {
+        bail!("Asar archive ended unexpectedly");
+    }
+    Ok(data)
+}
+
+/// Reads a single file from an asar archive.
+///
+/// The archive is read front to back without seeking, so this works on a tar entry as well as on
+/// a file on disk.
+pub fn read_file<R: Read>(mut reader: R, path: &str) -> Result<Vec<u8>> {
+    let header = read_header(&mut reader)?;
+    let (offset, size) = find_file(&header, path)?;
+
+    let skip = (header.data_start - header.consumed)
+        .checked_add(offset)
+        .context("Invalid file offset in asar archive")?;
+    let skipped = io::copy(&mut (&mut reader).take(skip), &mut io::sink())
+        .context("Failed to read asar archive")?;
+    if skipped != skip {
+        bail!("Asar archive ended unexpectedly");
+    }
+    read_data(reader, size)
+}
+
+/// Reads a single file from an asar archive on disk, seeking right to it
+pub fn read_file_at(archive: &Path, path: &str) -> Result<Vec<u8>> {
+    let file = File::open(archive).with_context(|| anyhow!("Failed to open {:?}", archive))?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader)?;
+    let (offset, size) = find_file(&header, path)?;
+
+    let start = header
+        .data_start
+        .checked_add(offset)
+        .context("Invalid file offset in asar archive")?;
+    reader
+        .seek(SeekFrom::Start(start))
+        .context("Failed to read asar archive")?;
+    read_data(reader, size)
+}
+
+fn parse_package_json(data: &[u8]) -> Result<PackageJson> {
+    serde_json::from_slice(data).context("Failed to parse package.json")
+}
+
+/// Reads the `package.json` of the app from an asar archive
+pub fn read_package_json<R: Read>(reader: R) -> Result<PackageJson> {
+    parse_package_json(&read_file(reader, "package.json")?)
+}
+
+/// Reads the `package.json` of the app from an asar archive on disk
+pub fn read_package_json_at(archive: &Path) -> Result<PackageJson> {
+    parse_package_json(&read_file_at(archive, "package.json")?)
+}
+
+/// Reads the `package.json` of the app installed in `install_path`.
+///
+/// Returns `None` if the installation has no `resources/app.asar`.
+pub async fn installed_package(install_path: &Path) -> Result<Option<PackageJson>> {
+    let archive = install_path.join(APP_ASAR);
+    if !tokio::fs::try_exists(&archive).await.unwrap_or(false) {
+        return Ok(None);
+    }
+    let package = task::spawn_blocking(move || {
+        read_package_json_at(&archive).with_context(|| anyhow!("Failed to read {:?}", archive))
+    })
+    .await??;
+    Ok(Some(package))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::{Map, Value, json};
+
+    /// Builds an asar archive the way Electron does, `files` may contain directories
+    pub(crate) fn asar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut root = Map::new();
+        let mut data = Vec::new();
+        for (path, content) in files {
+            let (dirs, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let mut dir = &mut root;
+            for part in dirs.split('/').filter(|part| !part.is_empty()) {
+                dir = dir.entry(part).or_insert_with(|| json!({ "files": {} }))["files"]
+                    .as_object_mut()
+                    .unwrap();
+            }
+            dir.insert(
+                name.to_string(),
+                json!({ "size": content.len(), "offset": data.len().to_string() }),
+            );
+            data.extend_from_slice(content);
+        }
+
+        encode(&json!({ "files": Value::Object(root) }), &data)
+    }
+
+    /// Puts the pickled json `header` in front of `data`
+    fn encode(header: &Value, data: &[u8]) -> Vec<u8> {
+        let json = serde_json::to_vec(header).unwrap();
+        let padding = (4 - json.len() % 4) % 4;
+        let payload_size = 4 + json.len() + padding;
+        let mut asar = Vec::new();
+        for field in [4, payload_size + 4, payload_size, json.len()] {
+            asar.extend((field as u32).to_le_bytes());
+        }
+        asar.extend(json);
+        asar.extend(vec![0; padding]);
+        asar.extend(data);
+        asar
+    }
+
+    #[test]
+    fn test_read_file() -> Result<()> {
+        let archive = asar(&[
+            ("index.js", b"require('electron')"),
+            ("dist/main.js", b"console.log()"),
+            (
+                "package.json",
+                br#"{"name":"linkchats","productName":"MTS Link","version":"1.4.2"}"#,
+            ),
+        ]);
+
+        assert_eq!(read_file(&archive[..], "dist/main.js")?, b"console.log()");
+        assert_eq!(
+            read_package_json(&archive[..])?,
+            PackageJson {
+                name: Some("linkchats".to_string()),
+                version: "1.4.2".to_string(),
+                product_name: Some("MTS Link".to_string()),
+            }
+        );
+        assert!(read_file(&archive[..], "missing.js").is_err());
+        assert!(read_file(&archive[..], "dist").is_err());
+        assert!(read_file(&archive[..archive.len() - 5], "package.json").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_offset() -> Result<()> {
+        let header = json!({
+            "files": { "package.json": { "size": 2, "offset": "18446744073709551615" } }
+        });
+        let archive = encode(&header, b"{}");
+
+        let err = read_file(&archive[..], "package.json").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid file offset in asar archive");
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.asar");
+        std::fs::write(&path, &archive)?;
+        let err = read_file_at(&path, "package.json").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid file offset in asar archive");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_installed_package() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(installed_package(dir.path()).await?, None);
+
+        let archive = asar(&[
+            ("index.js", b"require('electron')"),
+            ("package.json", br#"{"name":"linkchats","version":"1.4.2"}"#),
+        ]);
+        std::fs::create_dir(dir.path().join("resources"))?;
+        std::fs::write(dir.path().join(APP_ASAR), archive)?;
+
+        let package = installed_package(dir.path()).await?.unwrap();
+        assert_eq!(package.version, "1.4.2");
+        assert_eq!(package.product_name, None);
+        assert_eq!(
+            read_file_at(&dir.path().join(APP_ASAR), "index.js")?,
+            b"require('electron')"
+        );
+        Ok(())
+    }
+}
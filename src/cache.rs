@@ -0,0 +1,258 @@
+use crate::compression;
+use crate::config::Config;
+use crate::errors::*;
+use crate::units;
+use crate::version::Version;
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::fs;
+
+const PREFIX: &str = "linkchats-desktop-";
+/// Extensions of the archives in the cache, one per compression and one for `.deb` packages
+const SUFFIXES: &[&str] = &[".tar.gz", ".tar.xz", ".tar.zst", ".tar", ".deb"];
+
+/// An archive kept in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedArchive {
+    pub path: PathBuf,
+    pub version: Version,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Keeps installed archives around, keyed by their version, so reinstalling them does not need
+/// the network.
+#[derive(Debug)]
+pub struct ArchiveCache {
+    path: PathBuf,
+    keep_versions: usize,
+    max_size: Option<u64>,
+}
+
+impl ArchiveCache {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            path: config.archive_cache_path.clone(),
+            keep_versions: config.cache_keep_versions,
+            max_size: config.cache_max_size,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.keep_versions > 0
+    }
+
+    /// Returns the path the archive of `version` is stored at, `suffix` is its file extension
+    pub fn archive_path(&self, version: &Version, suffix: &str) -> PathBuf {
+        self.path.join(format!("{PREFIX}{version}{suffix}"))
+    }
+
+    /// Lists the cached archives, most recently stored first
+    pub async fn list(&self) -> Result<Vec<CachedArchive>> {
+        let mut archives = Vec::new();
+        let mut dir = match fs::read_dir(&self.path).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(archives),
+            Err(err) => {
+                return Err(Error::new(err)
+                    .context(anyhow!("Failed to read archive cache {:?}", self.path)));
+            }
+        };
+
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let Some(version) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(PREFIX))
+                .and_then(|name| {
+                    SUFFIXES
+                        .iter()
+                        .find_map(|suffix| Some((name.strip_suffix(suffix)?, *suffix)))
+                })
+                // packages are named after their Debian version
+                .and_then(|(version, suffix)| match suffix {
+                    ".deb" => Version::from_debian(version).ok(),
+                    _ => version.parse::<Version>().ok(),
+                })
+            else {
+                continue;
+            };
+
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            archives.push(CachedArchive {
+                path: entry.path(),
+                version,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        archives.sort_by_key(|archive| Reverse(archive.modified));
+        Ok(archives)
+    }
+
+    /// Stores the archive of `version` in the cache and applies the retention policy.
+    ///
+    /// With `take` the archive is moved into the cache, otherwise it is copied.
+    pub async fn store(&self, archive: &Path, version: &Version, take: bool) -> Result<PathBuf> {
+        fs::create_dir_all(&self.path)
+            .await
+            .with_context(|| anyhow!("Failed to create archive cache {:?}", self.path))?;
+
+        let target = self.archive_path(version, compression::archive_extension(archive)?);
+        let same_file = match (
+            fs::canonicalize(archive).await,
+            fs::canonicalize(&target).await,
+        ) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        };
+
+        if !same_file {
+            info!("Keeping archive of version {:?} at {:?}", version, target);
+            let moved = take && fs::rename(archive, &target).await.is_ok();
+            if !moved {
+                // copy under a temporary name, so an interrupted copy is never mistaken for an archive
+                let tmp = target.with_extension("tmp");
+                fs::copy(archive, &tmp)
+                    .await
+                    .with_context(|| anyhow!("Failed to copy {:?} to {:?}", archive, tmp))?;
+                fs::rename(&tmp, &target)
+                    .await
+                    .with_context(|| anyhow!("Failed to rename {:?} to {:?}", tmp, target))?;
+                if take {
+                    fs::remove_file(archive).await.ok();
+                }
+            }
+        }
+
+        // mark it as the most recent archive, the retention policy goes by modification time
+        let file = fs::File::options().append(true).open(&target).await?;
+        file.into_std()
+            .await
+            .set_modified(SystemTime::now())
+            .with_context(|| anyhow!("Failed to update modification time of {:?}", target))?;
+
+        // a copy of the same version with a different compression is superseded
+        for other in self.list().await? {
+            if other.path != target && other.version == *version {
+                fs::remove_file(&other.path)
+                    .await
+                    .with_context(|| anyhow!("Failed to remove {:?}", other.path))?;
+            }
+        }
+
+        self.prune(&target).await?;
+        Ok(target)
+    }
+
+    /// Removes old archives beyond the configured number of versions and total size.
+    /// The archive at `keep` is never removed.
+    pub async fn prune(&self, keep: &Path) -> Result<()> {
+        let archives = self.list().await?;
+        let mut total = 0;
+        let mut kept = 0;
+        for archive in archives {
+            let within_limits = kept < self.keep_versions
+                && self
+                    .max_size
+                    .is_none_or(|max_size| total + archive.size <= max_size);
+
+            if archive.path == keep || within_limits {
+                kept += 1;
+                total += archive.size;
+                continue;
+            }
+
+            info!(
+                "Removing cached archive of version {:?} ({})",
+                archive.version,
+                units::format_size(archive.size)
+            );
+            fs::remove_file(&archive.path)
+                .await
+                .with_context(|| anyhow!("Failed to remove {:?}", archive.path))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cache(path: &Path, keep_versions: usize, max_size: Option<u64>) -> ArchiveCache {
+        ArchiveCache {
+            path: path.to_path_buf(),
+            keep_versions,
+            max_size,
+        }
+    }
+
+    async fn store_all(cache: &ArchiveCache, versions: &[(&str, usize)]) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for (version, size) in versions {
+            let archive = dir.path().join("download.tar.gz");
+            fs::write(&archive, vec![0; *size]).await?;
+            cache.store(&archive, &version.parse()?, true).await?;
+            assert!(!archive.exists());
+            // keep the modification times apart on coarse filesystems
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+
+    fn versions(archives: &[CachedArchive]) -> Vec<String> {
+        archives.iter().map(|a| a.version.to_string()).collect()
+    }
+
+    #[test]
+    fn test_archive_path() -> Result<()> {
+        let cache = cache(Path::new("/cache"), 1, None);
+        assert_eq!(
+            cache.archive_path(&"1.2.3/".parse()?, ".tar.gz"),
+            Path::new("/cache/linkchats-desktop-1.2.3.tar.gz")
+        );
+        assert_eq!(
+            cache.archive_path(&"1.2.3".parse()?, ".tar.zst"),
+            Path::new("/cache/linkchats-desktop-1.2.3.tar.zst")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keep_versions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = cache(dir.path(), 2, None);
+        store_all(&cache, &[("1.0.0", 10), ("1.1.0", 10), ("1.2.0", 10)]).await?;
+        assert_eq!(versions(&cache.list().await?), ["1.2.0", "1.1.0"]);
+
+        // storing an old version again makes it the most recent one
+        let archive = dir.path().join("reinstall.tar.gz");
+        fs::copy(cache.archive_path(&"1.1.0".parse()?, ".tar"), &archive).await?;
+        cache.store(&archive, &"1.1.0".parse()?, false).await?;
+        assert!(archive.exists());
+        assert_eq!(versions(&cache.list().await?), ["1.1.0", "1.2.0"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_size() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = cache(dir.path(), 10, Some(25));
+        store_all(&cache, &[("1.0.0", 10), ("1.1.0", 10), ("1.2.0", 10)]).await?;
+        assert_eq!(versions(&cache.list().await?), ["1.2.0", "1.1.0"]);
+
+        // the archive that was just stored is kept even if it is too large on its own
+        store_all(&cache, &[("2.0.0", 30)]).await?;
+        assert_eq!(versions(&cache.list().await?), ["2.0.0"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::deb;
+use crate::errors::*;
+use libflate::gzip::Decoder;
+use lzma_rust2::XzReader;
+use ruzstd::decoding::StreamingDecoder;
+use std::{
+    fs::File,
+    io::{self, Chain, Cursor, Read},
+    path::Path,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// Length of the longest magic number, including the one of `.deb` packages
+const MAGIC_LEN: usize = 8;
+
+/// File extensions of the archives that can be installed
+pub const EXTENSIONS: &[&str] = &[
+    ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.zst", ".tzst", ".tar", ".deb",
+];
+
+/// Compression of a tar archive, detected from its magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    pub fn from_magic(magic: &[u8]) -> Self {
+        if magic.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(XZ_MAGIC) {
+            Compression::Xz
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// File extension of a tar archive with this compression
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".tar.gz",
+            Compression::Xz => ".tar.xz",
+            Compression::Zstd => ".tar.zst",
+            Compression::None => ".tar",
+        }
+    }
+}
+
+/// A reader with its first bytes put back in front of it
+pub type Peeked<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Reads the magic bytes at the start of `reader`, returning them along with a reader that still
+/// starts at the beginning
+pub fn peek<R: Read>(mut reader: R) -> io::Result<(Vec<u8>, Peeked<R>)> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    (&mut reader)
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    Ok((magic.clone(), Cursor::new(magic).chain(reader)))
+}
+
+/// Returns the file extension matching the content of an archive or package
+pub fn archive_extension(path: &Path) -> Result<&'static str> {
+    let file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let (magic, _) = peek(file).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    if magic.starts_with(deb::AR_MAGIC) {
+        Ok(".deb")
+    } else {
+        Ok(Compression::from_magic(&magic).extension())
+    }
+}
+
+/// Detects the compression of `reader`, returning a reader of the plain tar stream
+pub fn decompress<'a, R: Read + 'a>(reader: R) -> Result<(Compression, Box<dyn Read + 'a>)> {
+    let (magic, reader) = peek(reader).context("Failed to read archive")?;
+    let compression = Compression::from_magic(&magic);
+    debug!("Archive has {:?} compression", compression);
+
+    let reader: Box<dyn Read> = match compression {
+        Compression::Gzip => {
+            Box::new(Decoder::new(reader).context("Failed to decode gzip archive")?)
+        }
+        Compression::Xz => Box::new(XzReader::new(reader, true)),
+        Compression::Zstd => Box::new(
+            StreamingDecoder::new(reader)
+                .map_err(|err| anyhow!("Failed to decode zstd archive: {err}"))?,
+        ),
+        Compression::None => Box::new(reader),
+    };
+    Ok((compression, reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const DATA: &[u8] = b"not really a tar archive, but the decoders do not care";
+
+    fn roundtrip(compressed: Vec<u8>, expected: Compression) -> Result<()> {
+        let (compression, mut reader) = decompress(&compressed[..])?;
+        assert_eq!(compression, expected);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(buf, DATA);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip() -> Result<()> {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+        encoder.write_all(DATA)?;
+        roundtrip(encoder.finish().into_result()?, Compression::Gzip)
+    }
+
+    #[test]
+    fn test_xz() -> Result<()> {
+        let mut writer = lzma_rust2::XzWriter::new(Vec::new(), Default::default())?;
+        writer.write_all(DATA)?;
+        roundtrip(writer.finish()?, Compression::Xz)
+    }
+
+    #[test]
+    fn test_zstd() -> Result<()> {
+        let compressed =
+            ruzstd::encoding::compress_to_vec(DATA, ruzstd::encoding::CompressionLevel::Fastest);
+        roundtrip(compressed, Compression::Zstd)
+    }
+
+    #[test]
+    fn test_plain() -> Result<()> {
+        roundtrip(DATA.to_vec(), Compression::None)?;
+        // shorter than any magic number
+        let (compression, mut reader) = decompress(&b"ab"[..])?;
+        assert_eq!(compression, Compression::None);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(buf, b"ab");
+        Ok(())
+    }
+}
@@ -1,8 +1,16 @@
+use crate::apt::Repository;
 use crate::args::Args;
+use crate::deb;
 use crate::errors::*;
 use crate::paths::Paths;
+use crate::pkg;
+use crate::retry::RetryPolicy;
+use crate::signature::SignaturePolicy;
+use crate::units;
+use crate::version::Version;
 use file::ConfigFile;
-use std::path::PathBuf;
+use regex::Regex;
+use std::{path::PathBuf, time::Duration};
 
 mod file;
 
@@ -12,17 +20,76 @@ pub const BIN_APP_NAME: &str = "mtslink.bin";
 pub struct Config {
     pub install_path: PathBuf,
     pub new_intsall_path: PathBuf,
+    pub staged_path: PathBuf,
     pub state_path: PathBuf,
     pub cache_path: PathBuf,
+    pub download_path: PathBuf,
+    pub archive_cache_path: PathBuf,
+    pub systemd_unit_path: PathBuf,
+    /// Number of installed archives to keep, 0 disables the cache
+    pub cache_keep_versions: usize,
+    /// Bytes
+    pub cache_max_size: Option<u64>,
     pub download_attempts: usize,
+    pub retry_policy: RetryPolicy,
+    /// Bytes per second
+    pub max_download_rate: Option<u64>,
+    pub parallel_downloads: usize,
+    pub offline: bool,
+    pub background_update: bool,
+    pub relaunch_after_update: bool,
+    /// Install versions older than the installed one
+    pub allow_downgrade: bool,
+    /// Only ever install this version
+    pub pin_version: Option<Version>,
+    /// Keep the installed version, do not check for updates. Without an installed version the
+    /// latest one is still installed, there would be nothing to launch otherwise
+    pub hold: bool,
+    pub offline_archive_dir: Option<PathBuf>,
+    /// Finds the version in the name of the top level directory of an archive
+    pub version_regex: Regex,
     pub check_update: bool,
     pub force_check_update: bool,
     pub check_update_interval: usize,
     pub extra_arguments: Vec<String>,
     pub tar_path: Option<PathBuf>,
+    pub sources: Vec<String>,
+    /// Debian repository used instead of `sources`
+    pub apt_repository: Option<Repository>,
+    pub proxy: Option<String>,
+    pub proxy_auth: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub ca_certificates: Vec<PathBuf>,
+    pub pinned_public_keys: Vec<String>,
+    /// Hosts the public keys are pinned for
+    pub pinned_hosts: Vec<String>,
+    pub sha256: Option<String>,
+    pub sha256_sidecar: bool,
+    pub checksum_file: Option<PathBuf>,
+    pub minisign_public_keys: Vec<String>,
+    pub openpgp_keyrings: Vec<PathBuf>,
+    pub signature_policy: SignaturePolicy,
     pub timeout: Option<usize>,
 }
 
+/// Hosts of the remote sources and the repository, where public keys are pinned by default
+fn source_hosts(sources: &[String], repository: Option<&Repository>) -> Vec<String> {
+    let mut hosts = Vec::new();
+    for url in sources
+        .iter()
+        .chain(repository.map(|repository| &repository.url))
+    {
+        if let Some(host) = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+            && !hosts.contains(&host)
+        {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
 #[derive(Debug)]
 pub struct ConfigBuilder<'a> {
     args: &'a Args,
@@ -57,15 +124,158 @@ impl Config {
     pub fn new(args: &Args, cf: &ConfigFile) -> Result<Self> {
         let paths = Paths::new()?;
 
+        let default_retry_policy = RetryPolicy::default();
+        let retry_delay = |value: Option<f64>, default: Duration| {
+            value
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .map(|value| value.unwrap_or(default))
+        };
+        let retry_policy = RetryPolicy {
+            initial_delay: retry_delay(
+                cf.launcher.retry_initial_delay,
+                default_retry_policy.initial_delay,
+            )
+            .context("Invalid retry_initial_delay in config file")?,
+            multiplier: cf
+                .launcher
+                .retry_multiplier
+                .unwrap_or(default_retry_policy.multiplier),
+            max_delay: retry_delay(cf.launcher.retry_max_delay, default_retry_policy.max_delay)
+                .context("Invalid retry_max_delay in config file")?,
+            jitter: cf
+                .launcher
+                .retry_jitter
+                .unwrap_or(default_retry_policy.jitter),
+        };
+        retry_policy
+            .validate()
+            .context("Invalid retry settings in config file")?;
+
+        let max_download_rate = args
+            .max_download_rate
+            .as_ref()
+            .or(cf.launcher.max_download_rate.as_ref())
+            .map(|rate| units::parse_rate(rate))
+            .transpose()
+            .context("Invalid max_download_rate")?;
+
+        let cache_max_size = cf
+            .launcher
+            .cache_max_size
+            .as_deref()
+            .map(units::parse_size)
+            .transpose()
+            .context("Invalid cache_max_size")?;
+
+        let sources = if !args.sources.is_empty() {
+            args.sources.clone()
+        } else if let Some(sources) = &cf.launcher.sources {
+            sources.clone()
+        } else {
+            vec![pkg::DOWNLOAD_URL.to_string()]
+        };
+
+        // an empty list would silently turn pinning off
+        if cf
+            .launcher
+            .pinned_public_keys
+            .as_ref()
+            .is_some_and(Vec::is_empty)
+        {
+            bail!("pinned_public_keys is empty, remove it to disable pinning");
+        }
+        if cf.launcher.pinned_hosts.as_ref().is_some_and(Vec::is_empty) {
+            bail!("pinned_hosts is empty, remove it to pin the hosts of the sources");
+        }
+
+        let pin_version = args
+            .pin_version
+            .as_deref()
+            .or(cf.launcher.pin_version.as_deref())
+            .map(str::parse::<Version>)
+            .transpose()
+            .context("Invalid pin_version")?;
+
+        let version_regex = Regex::new(
+            cf.launcher
+                .version_regex
+                .as_deref()
+                .unwrap_or(pkg::VERSION_REGEX),
+        )
+        .context("Invalid version_regex")?;
+
+        // sources given on the command line win over a repository from the config file
+        let apt_repository = cf
+            .launcher
+            .apt_repository
+            .clone()
+            .filter(|_| args.sources.is_empty())
+            .map(|url| Repository {
+                url,
+                suite: cf
+                    .launcher
+                    .apt_suite
+                    .clone()
+                    .unwrap_or_else(|| "stable".to_string()),
+                component: cf
+                    .launcher
+                    .apt_component
+                    .clone()
+                    .unwrap_or_else(|| "main".to_string()),
+                package: cf
+                    .launcher
+                    .apt_package
+                    .clone()
+                    .unwrap_or_else(|| pkg::DEB_PACKAGE.to_string()),
+                architecture: cf
+                    .launcher
+                    .apt_architecture
+                    .clone()
+                    .unwrap_or_else(|| deb::host_architecture().to_string()),
+            });
+
         Ok(Self {
             install_path: args.install_dir.clone().unwrap_or(paths.install),
             new_intsall_path: args.install_dir.clone().unwrap_or(paths.new_install),
+            staged_path: args
+                .install_dir
+                .as_ref()
+                .map(|dir| {
+                    let mut path = dir.as_os_str().to_owned();
+                    path.push("-staged");
+                    PathBuf::from(path)
+                })
+                .unwrap_or(paths.staged),
             state_path: paths.state,
             cache_path: paths.cache,
+            download_path: paths.download,
+            archive_cache_path: paths.archives,
+            systemd_unit_path: paths.systemd_units,
+            cache_keep_versions: cf.launcher.cache_keep_versions.unwrap_or(3),
+            cache_max_size,
             download_attempts: args
                 .download_attempts
                 .or(cf.launcher.download_attempts)
                 .unwrap_or(5),
+            retry_policy,
+            max_download_rate,
+            parallel_downloads: args
+                .parallel_downloads
+                .or(cf.launcher.parallel_downloads)
+                .unwrap_or(1),
+            offline: args.offline || cf.launcher.offline.unwrap_or(false),
+            offline_archive_dir: cf.launcher.offline_archive_dir.clone(),
+            version_regex,
+            background_update: args.background_update
+                || cf.launcher.background_update.unwrap_or(false),
+            relaunch_after_update: cf.launcher.relaunch_after_update.unwrap_or(false),
+            // an archive passed with --tar is installed on purpose
+            allow_downgrade: args.allow_downgrade
+                || args.tar.is_some()
+                || cf.launcher.allow_downgrade.unwrap_or(false),
+            pin_version,
+            hold: args.hold || cf.launcher.hold.unwrap_or(false),
             check_update: if args.skip_check_update {
                 false
             } else {
@@ -77,17 +287,42 @@ impl Config {
                 .unwrap_or(cf.launcher.check_update_interval),
             extra_arguments: cf.mts_linkchats.extra_arguments.clone(),
             tar_path: args.tar.clone(),
+            pinned_hosts: match &cf.launcher.pinned_hosts {
+                Some(hosts) => hosts.clone(),
+                None => source_hosts(&sources, apt_repository.as_ref()),
+            },
+            sources,
+            apt_repository,
+            proxy: args.proxy.clone().or_else(|| cf.launcher.proxy.clone()),
+            proxy_auth: args
+                .proxy_auth
+                .clone()
+                .or_else(|| cf.launcher.proxy_auth.clone()),
+            no_proxy: if !args.no_proxy.is_empty() {
+                args.no_proxy.clone()
+            } else {
+                cf.launcher.no_proxy.clone().unwrap_or_default()
+            },
+            ca_certificates: cf.launcher.ca_certificates.clone().unwrap_or_default(),
+            pinned_public_keys: cf.launcher.pinned_public_keys.clone().unwrap_or_default(),
+            sha256: args.sha256.clone().or_else(|| cf.launcher.sha256.clone()),
+            sha256_sidecar: cf.launcher.sha256_sidecar.unwrap_or(false),
+            checksum_file: cf.launcher.checksum_file.clone(),
+            minisign_public_keys: cf.launcher.minisign_public_keys.clone().unwrap_or_default(),
+            openpgp_keyrings: cf.launcher.openpgp_keyrings.clone().unwrap_or_default(),
+            signature_policy: cf.launcher.signature_policy.unwrap_or_default(),
             timeout: args.timeout,
         })
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     fn get_default_args() -> Args {
         Args {
+            command: None,
             skip_check_update: true,
             timeout: None,
             tar: None,
@@ -96,10 +331,42 @@ mod tests {
             download_attempts: None,
             verbose: 0,
             print_tar_url: false,
+            sources: vec![],
+            proxy: None,
+            proxy_auth: None,
+            no_proxy: vec![],
+            max_download_rate: None,
+            parallel_downloads: None,
+            sha256: None,
+            offline: false,
+            background_update: false,
+            allow_downgrade: false,
+            pin_version: None,
+            hold: false,
             no_exec: true,
         }
     }
 
+    /// The configuration with every setting left at its default
+    pub(crate) fn config() -> Result<Config> {
+        Config::new(&get_default_args(), &ConfigFile::parse("")?)
+    }
+
+    /// The default configuration, with the installation, state and caches inside `dir`
+    pub(crate) fn config_in(dir: &std::path::Path) -> Result<Config> {
+        Ok(Config {
+            install_path: dir.join("install"),
+            new_intsall_path: dir.join("install-new"),
+            staged_path: dir.join("install-staged"),
+            state_path: dir.join("state.toml"),
+            cache_path: dir.to_path_buf(),
+            download_path: dir.join("downloads"),
+            archive_cache_path: dir.join("archives"),
+            systemd_unit_path: dir.join("systemd"),
+            ..config()?
+        })
+    }
+
     #[test]
     fn check_overrided_args_over_config_file() -> Result<()> {
         let args = Args {
@@ -129,4 +396,231 @@ download_attempts = 2
 
         Ok(())
     }
+
+    #[test]
+    fn check_retry_policy_from_config_file() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+retry_initial_delay = 0.5
+retry_multiplier = 3
+retry_max_delay = 30
+        "#,
+        )?;
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+
+        assert_eq!(
+            config.retry_policy,
+            RetryPolicy {
+                initial_delay: Duration::from_millis(500),
+                multiplier: 3.0,
+                max_delay: Duration::from_secs(30),
+                jitter: RetryPolicy::default().jitter,
+            }
+        );
+
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+retry_jitter = 2.0
+        "#,
+        )?;
+        assert!(Config::builder(&args).config_file(&cf).build().is_err());
+
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+retry_max_delay = -1
+        "#,
+        )?;
+        assert!(Config::builder(&args).config_file(&cf).build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_max_download_rate() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+max_download_rate = "2MiB/s"
+        "#,
+        )?;
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.max_download_rate, Some(2 * 1024 * 1024));
+
+        let args = Args {
+            max_download_rate: Some("500KB/s".to_string()),
+            ..get_default_args()
+        };
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.max_download_rate, Some(500_000));
+
+        let args = Args {
+            max_download_rate: Some("fast".to_string()),
+            ..get_default_args()
+        };
+        assert!(Config::builder(&args).config_file(&cf).build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_offline() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+offline = true
+        "#,
+        )?;
+        let args = get_default_args();
+        assert!(Config::builder(&args).config_file(&cf).build()?.offline);
+
+        let args = Args {
+            offline: true,
+            ..get_default_args()
+        };
+        let config = Config::builder(&args)
+            .config_file(&ConfigFile::default())
+            .build()?;
+        assert!(config.offline);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_sources_override() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+sources = ["https://mirror.example.com/linkchats.tar.gz"]
+        "#,
+        )?;
+
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(
+            config.sources,
+            ["https://mirror.example.com/linkchats.tar.gz"]
+        );
+
+        let args = Args {
+            sources: vec!["https://other.example.com/linkchats.tar.gz".to_string()],
+            ..get_default_args()
+        };
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(
+            config.sources,
+            ["https://other.example.com/linkchats.tar.gz"]
+        );
+
+        let config = Config::builder(&args)
+            .config_file(&ConfigFile::default())
+            .build()?;
+        assert_eq!(
+            config.sources,
+            ["https://other.example.com/linkchats.tar.gz"]
+        );
+
+        let args = get_default_args();
+        let config = Config::builder(&args)
+            .config_file(&ConfigFile::default())
+            .build()?;
+        assert_eq!(config.sources, [pkg::DOWNLOAD_URL]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_apt_repository() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+apt_repository = "https://apt.example.com/debian"
+apt_suite = "bookworm"
+        "#,
+        )?;
+
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(
+            config.apt_repository,
+            Some(Repository {
+                url: "https://apt.example.com/debian".to_string(),
+                suite: "bookworm".to_string(),
+                component: "main".to_string(),
+                package: pkg::DEB_PACKAGE.to_string(),
+                architecture: deb::host_architecture().to_string(),
+            })
+        );
+
+        let args = Args {
+            sources: vec!["https://other.example.com/linkchats.tar.gz".to_string()],
+            ..get_default_args()
+        };
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.apt_repository, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_pinned_hosts() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+sources = ["https://Mirror.example.com/linkchats.tar.gz", "/mnt/share", "https://mirror.example.com/b.tar.gz"]
+apt_repository = "https://apt.example.com/debian"
+pinned_public_keys = ["sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="]
+        "#,
+        )?;
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(
+            config.pinned_hosts,
+            ["mirror.example.com", "apt.example.com"]
+        );
+
+        let cf = ConfigFile::parse("[launcher]\npinned_hosts = [\"cdn.example.com\"]")?;
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.pinned_hosts, ["cdn.example.com"]);
+
+        for empty in ["pinned_public_keys = []", "pinned_hosts = []"] {
+            let cf = ConfigFile::parse(&format!("[launcher]\n{empty}"))?;
+            assert!(Config::builder(&args).config_file(&cf).build().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_pin_version() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+pin_version = "1.9.0"
+hold = true
+        "#,
+        )?;
+
+        let args = get_default_args();
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.pin_version, Some("1.9.0".parse()?));
+        assert!(config.hold);
+
+        let args = Args {
+            pin_version: Some("1.10.0".to_string()),
+            ..get_default_args()
+        };
+        let config = Config::builder(&args).config_file(&cf).build()?;
+        assert_eq!(config.pin_version, Some("1.10.0".parse()?));
+
+        let args = Args {
+            pin_version: Some("latest".to_string()),
+            ..get_default_args()
+        };
+        assert!(Config::builder(&args).config_file(&cf).build().is_err());
+        Ok(())
+    }
 }
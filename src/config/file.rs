@@ -1,4 +1,5 @@
 use crate::errors::*;
+use crate::signature::SignaturePolicy;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -99,6 +100,40 @@ pub struct LauncherConfig {
     #[serde(default)]
     pub check_update_interval: usize,
     pub download_attempts: Option<usize>,
+    pub retry_initial_delay: Option<f64>,
+    pub retry_multiplier: Option<f64>,
+    pub retry_max_delay: Option<f64>,
+    pub retry_jitter: Option<f64>,
+    pub max_download_rate: Option<String>,
+    pub parallel_downloads: Option<usize>,
+    pub cache_keep_versions: Option<usize>,
+    pub cache_max_size: Option<String>,
+    pub offline: Option<bool>,
+    pub offline_archive_dir: Option<PathBuf>,
+    pub background_update: Option<bool>,
+    pub relaunch_after_update: Option<bool>,
+    pub allow_downgrade: Option<bool>,
+    pub version_regex: Option<String>,
+    pub pin_version: Option<String>,
+    pub hold: Option<bool>,
+    pub sources: Option<Vec<String>>,
+    pub apt_repository: Option<String>,
+    pub apt_suite: Option<String>,
+    pub apt_component: Option<String>,
+    pub apt_package: Option<String>,
+    pub apt_architecture: Option<String>,
+    pub proxy: Option<String>,
+    pub proxy_auth: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    pub ca_certificates: Option<Vec<PathBuf>>,
+    pub pinned_public_keys: Option<Vec<String>>,
+    pub pinned_hosts: Option<Vec<String>>,
+    pub sha256: Option<String>,
+    pub sha256_sidecar: Option<bool>,
+    pub checksum_file: Option<PathBuf>,
+    pub minisign_public_keys: Option<Vec<String>>,
+    pub openpgp_keyrings: Option<Vec<PathBuf>>,
+    pub signature_policy: Option<SignaturePolicy>,
 }
 
 #[cfg(test)]
@@ -140,6 +175,45 @@ check_update_interval = 0
         Ok(())
     }
 
+    #[test]
+    fn test_sources_config() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+sources = ["https://mirror.example.com/linkchats.tar.gz", "https://apps.webinar.ru/weteams/linkchats-desktop.tar.gz"]
+        "#,
+        )?;
+        assert_eq!(
+            cf.launcher.sources,
+            Some(vec![
+                "https://mirror.example.com/linkchats.tar.gz".to_string(),
+                "https://apps.webinar.ru/weteams/linkchats-desktop.tar.gz".to_string(),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_policy_config() -> Result<()> {
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+minisign_public_keys = ["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"]
+signature_policy = "warn"
+        "#,
+        )?;
+        assert_eq!(cf.launcher.signature_policy, Some(SignaturePolicy::Warn));
+
+        let cf = ConfigFile::parse(
+            r#"
+[launcher]
+signature_policy = "maybe"
+        "#,
+        );
+        assert!(cf.is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_check_update_interval_negative_config() -> Result<()> {
         let cf = ConfigFile::parse(
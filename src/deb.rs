@@ -0,0 +1,470 @@
+use crate::compression;
+use crate::errors::*;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io::{self, Read},
+};
+
+/// Magic bytes of the `ar` archive a `.deb` package is wrapped in
+pub const AR_MAGIC: &[u8] = b"!<arch>\n";
+/// Size of the header in front of every `ar` member
+const AR_HEADER_LEN: usize = 60;
+
+/// A paragraph of a deb822 control file, e.g. one package of a `Packages` index.
+///
+/// Field names are case insensitive and stored in lowercase, continuation lines are joined with
+/// newlines.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Paragraph(HashMap<String, String>);
+
+impl Paragraph {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(&field.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    fn require(&self, field: &str) -> Result<&str> {
+        self.get(field)
+            .with_context(|| anyhow!("Missing {:?} field", field))
+    }
+}
+
+/// Parses the paragraphs of a deb822 control file
+pub fn parse_paragraphs(text: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut fields = HashMap::new();
+    let mut last: Option<String> = None;
+
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !fields.is_empty() {
+                paragraphs.push(Paragraph(std::mem::take(&mut fields)));
+            }
+            last = None;
+        } else if line.starts_with('#') {
+            continue;
+        } else if line.starts_with([' ', '\t']) {
+            if let Some(value) = last.as_ref().and_then(|name| fields.get_mut(name)) {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            fields.insert(name.clone(), value.trim().to_string());
+            last = Some(name);
+        }
+    }
+    if !fields.is_empty() {
+        paragraphs.push(Paragraph(fields));
+    }
+    paragraphs
+}
+
+/// A file listed in the `SHA256` field of a `Release` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Parses the checksums of the index files from a `Release` file
+pub fn parse_release(text: &str) -> Result<Vec<IndexFile>> {
+    let release = parse_paragraphs(text)
+        .into_iter()
+        .next()
+        .context("Release file is empty")?;
+    let sha256 = release
+        .require("SHA256")
+        .context("Release file has no sha256 checksums")?;
+
+    sha256
+        .lines()
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let (Some(sha256), Some(size), Some(path)) = (parts.next(), parts.next(), parts.next())
+            else {
+                bail!("Invalid checksum line in Release file: {:?}", line);
+            };
+            Ok(IndexFile {
+                path: path.to_string(),
+                size: size
+                    .parse()
+                    .with_context(|| anyhow!("Invalid size in Release file: {:?}", line))?,
+                sha256: sha256.to_ascii_lowercase(),
+            })
+        })
+        .collect()
+}
+
+/// A package listed in a `Packages` index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub architecture: String,
+    /// Path of the `.deb` relative to the repository root
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Package {
+    fn from_paragraph(paragraph: &Paragraph) -> Result<Self> {
+        Ok(Package {
+            name: paragraph.require("Package")?.to_string(),
+            version: paragraph.require("Version")?.to_string(),
+            architecture: paragraph.require("Architecture")?.to_string(),
+            filename: paragraph.require("Filename")?.to_string(),
+            size: paragraph
+                .require("Size")?
+                .parse()
+                .context("Invalid Size field")?,
+            sha256: paragraph.require("SHA256")?.to_ascii_lowercase(),
+        })
+    }
+}
+
+/// Parses a `Packages` index, skipping paragraphs that lack anything needed for a download
+pub fn parse_packages(text: &str) -> Vec<Package> {
+    parse_paragraphs(text)
+        .iter()
+        .filter_map(|paragraph| {
+            Package::from_paragraph(paragraph)
+                .inspect_err(|err| {
+                    debug!(
+                        "Skipping package {:?} in index: {err:#}",
+                        paragraph.get("Package")
+                    )
+                })
+                .ok()
+        })
+        .collect()
+}
+
+/// Picks the highest version of `name` that can be installed on `architecture`
+pub fn newest_package<'a>(
+    packages: &'a [Package],
+    name: &str,
+    architecture: &str,
+) -> Option<&'a Package> {
+    packages
+        .iter()
+        .filter(|package| package.name == name)
+        .filter(|package| package.architecture == architecture || package.architecture == "all")
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+}
+
+/// Debian name of the architecture the launcher was built for
+pub fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "i386",
+        "aarch64" => "arm64",
+        "arm" => "armhf",
+        "powerpc64" => "ppc64el",
+        "loongarch64" => "loong64",
+        arch => arch,
+    }
+}
+
+/// Orders the characters of a non-digit part, `~` sorts before everything, even the end
+fn char_order(c: Option<char>) -> i32 {
+    match c {
+        Some('~') => -1,
+        None => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+/// Compares upstream versions or revisions the way dpkg does
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    while !a.is_empty() || !b.is_empty() {
+        // non-digit prefix, compared character by character
+        let a_len = a.find(|c: char| c.is_ascii_digit()).unwrap_or(a.len());
+        let b_len = b.find(|c: char| c.is_ascii_digit()).unwrap_or(b.len());
+        let (mut a_chars, mut b_chars) = (a[..a_len].chars(), b[..b_len].chars());
+        loop {
+            let (x, y) = (a_chars.next(), b_chars.next());
+            if x.is_none() && y.is_none() {
+                break;
+            }
+            let ordering = char_order(x).cmp(&char_order(y));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        (a, b) = (&a[a_len..], &b[b_len..]);
+
+        // digit prefix, compared numerically
+        let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+        let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+        let (x, y) = (
+            a[..a_len].trim_start_matches('0'),
+            b[..b_len].trim_start_matches('0'),
+        );
+        let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        (a, b) = (&a[a_len..], &b[b_len..]);
+    }
+    Ordering::Equal
+}
+
+/// Compares two Debian versions of the form `[epoch:]upstream[-revision]`
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn split(version: &str) -> (u64, &str, &str) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+        (epoch, upstream, revision)
+    }
+
+    let (a_epoch, a_upstream, a_revision) = split(a);
+    let (b_epoch, b_upstream, b_revision) = split(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_part(a_upstream, b_upstream))
+        .then_with(|| compare_part(a_revision, b_revision))
+}
+
+/// Reads the members of an `ar` archive one after another, like the `.deb` packages
+pub struct ArReader<R> {
+    inner: R,
+    /// Bytes left in the current member
+    remaining: u64,
+    /// Members are padded to an even size
+    padding: u64,
+}
+
+impl<R: Read> ArReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0; AR_MAGIC.len()];
+        inner
+            .read_exact(&mut magic)
+            .context("Failed to read package")?;
+        if magic != AR_MAGIC {
+            bail!("Package is not an ar archive");
+        }
+        Ok(Self {
+            inner,
+            remaining: 0,
+            padding: 0,
+        })
+    }
+
+    /// Skips the rest of the current member and returns the name of the next one.
+    /// The member is then read from the `ArReader` itself.
+    pub fn next_member(&mut self) -> Result<Option<String>> {
+        let skip = self.remaining + self.padding;
+        io::copy(&mut (&mut self.inner).take(skip), &mut io::sink())
+            .context("Failed to read package")?;
+        self.remaining = 0;
+        self.padding = 0;
+
+        let mut header = Vec::with_capacity(AR_HEADER_LEN);
+        (&mut self.inner)
+            .take(AR_HEADER_LEN as u64)
+            .read_to_end(&mut header)
+            .context("Failed to read package")?;
+        if header.is_empty() {
+            return Ok(None);
+        }
+        if header.len() < AR_HEADER_LEN || &header[58..] != b"`\n" {
+            bail!("Package contains an invalid ar header");
+        }
+
+        let name = String::from_utf8_lossy(&header[..16]);
+        let name = name.trim_end().trim_end_matches('/').to_string();
+        let size = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse::<u64>()
+            .with_context(|| anyhow!("Invalid size of package member {:?}", name))?;
+        self.remaining = size;
+        self.padding = size % 2;
+        Ok(Some(name))
+    }
+}
+
+impl<R: Read> Read for ArReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads the version from the `control` file of a `control.tar.*` member
+pub fn read_control<R: Read>(member: R) -> Result<String> {
+    let (_, decoder) = compression::decompress(member)?;
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive
+        .entries()
+        .context("Failed to read control archive")?
+    {
+        let mut entry = entry.context("Failed to read control archive")?;
+        let path = entry.path().context("Failed to read control archive")?;
+        if path.file_name().is_some_and(|name| name == "control") {
+            let mut text = String::new();
+            entry
+                .read_to_string(&mut text)
+                .context("Failed to read control file")?;
+            let control = parse_paragraphs(&text)
+                .into_iter()
+                .next()
+                .context("Control file is empty")?;
+            return Ok(control.require("Version")?.to_string());
+        }
+    }
+    bail!("Package has no control file")
+}
+
+/// Reads the version of a `.deb` package without unpacking it
+pub fn read_version<R: Read>(reader: R) -> Result<String> {
+    let mut ar = ArReader::new(reader)?;
+    while let Some(name) = ar.next_member()? {
+        if name.starts_with("control.tar") {
+            return read_control(&mut ar);
+        }
+    }
+    bail!("Package has no control archive")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::pkg::tests::tar;
+
+    /// Builds a `.deb` with a gzip compressed control archive and an uncompressed data archive
+    pub(crate) fn package(version: &str, files: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+        let control =
+            format!("Package: linkchats-desktop\nVersion: {version}\nArchitecture: amd64\n");
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+        io::Write::write_all(&mut encoder, &tar(&[("./control", control.as_bytes())])?)?;
+        let control = encoder.finish().into_result()?;
+
+        let mut deb = AR_MAGIC.to_vec();
+        for (name, data) in [
+            ("debian-binary", b"2.0\n".to_vec()),
+            ("control.tar.gz", control),
+            ("data.tar", tar(files)?),
+        ] {
+            deb.extend(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    name,
+                    0,
+                    0,
+                    0,
+                    100644,
+                    data.len()
+                )
+                .bytes(),
+            );
+            deb.extend(&data);
+            if data.len() % 2 == 1 {
+                deb.push(b'\n');
+            }
+        }
+        Ok(deb)
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        let cases = [
+            ("1.0", "1.0", Ordering::Equal),
+            ("1.10.0", "1.9.2", Ordering::Greater),
+            ("1.0~rc1", "1.0", Ordering::Less),
+            ("1.0", "1.0+deb1", Ordering::Less),
+            ("1:0.9", "2.0", Ordering::Greater),
+            ("1.0-2", "1.0-10", Ordering::Less),
+            ("1.0a", "1.0-1", Ordering::Greater),
+            ("2.3.4-1", "2.3.4-1ubuntu1", Ordering::Less),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(compare_versions(a, b), expected, "{a} <=> {b}");
+        }
+    }
+
+    #[test]
+    fn test_parse_release() -> Result<()> {
+        let files = parse_release(
+            "Origin: MTS\nSuite: stable\nSHA256:\n \
+             aa 120 main/binary-amd64/Packages\n \
+             BB 80 main/binary-amd64/Packages.gz\n",
+        )?;
+        assert_eq!(
+            files,
+            [
+                IndexFile {
+                    path: "main/binary-amd64/Packages".into(),
+                    size: 120,
+                    sha256: "aa".into(),
+                },
+                IndexFile {
+                    path: "main/binary-amd64/Packages.gz".into(),
+                    size: 80,
+                    sha256: "bb".into(),
+                },
+            ]
+        );
+        assert!(parse_release("Origin: MTS\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_newest_package() {
+        let packages = parse_packages(
+            "Package: linkchats-desktop\nVersion: 1.9.0-1\nArchitecture: amd64\n\
+             Filename: pool/l/linkchats_1.9.0-1_amd64.deb\nSize: 10\nSHA256: aa\n\
+             Description: chats\n more chats\n\n\
+             Package: linkchats-desktop\nVersion: 1.10.0-1\nArchitecture: amd64\n\
+             Filename: pool/l/linkchats_1.10.0-1_amd64.deb\nSize: 10\nSHA256: bb\n\n\
+             Package: linkchats-desktop\nVersion: 2.0.0-1\nArchitecture: arm64\n\
+             Filename: pool/l/linkchats_2.0.0-1_arm64.deb\nSize: 10\nSHA256: cc\n\n\
+             Package: linkchats-desktop\nVersion: 3.0.0-1\nArchitecture: amd64\n\n\
+             Package: other\nVersion: 9.0\nArchitecture: all\n\
+             Filename: pool/o/other_9.0_all.deb\nSize: 10\nSHA256: dd\n",
+        );
+        assert_eq!(packages.len(), 4);
+
+        let newest = newest_package(&packages, "linkchats-desktop", "amd64").unwrap();
+        assert_eq!(newest.version, "1.10.0-1");
+        assert_eq!(newest.filename, "pool/l/linkchats_1.10.0-1_amd64.deb");
+        assert_eq!(
+            newest_package(&packages, "other", "amd64").unwrap().version,
+            "9.0"
+        );
+        assert!(newest_package(&packages, "linkchats-desktop", "i386").is_none());
+    }
+
+    #[test]
+    fn test_read_version() -> Result<()> {
+        let deb = package("1.2.3-1", &[("./opt/linkchats/mtslink.bin", b"hello")])?;
+        assert_eq!(read_version(&deb[..])?, "1.2.3-1");
+
+        let mut ar = ArReader::new(&deb[..])?;
+        let mut names = Vec::new();
+        while let Some(name) = ar.next_member()? {
+            names.push(name);
+        }
+        assert_eq!(names, ["debian-binary", "control.tar.gz", "data.tar"]);
+        Ok(())
+    }
+}
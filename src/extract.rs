@@ -1,9 +1,22 @@
-use crate::config::Config;
+use crate::compression;
+use crate::config::{BIN_APP_NAME, Config};
+use crate::deb;
 use crate::errors::*;
-use libflate::gzip::Decoder;
-use std::io::Read;
-use std::path::Path;
-use tokio::fs;
+use crate::pkg::{self, VersionDetector, VersionSource};
+use crate::version::Version;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tempfile::TempDir;
+use tokio::{
+    fs,
+    io::AsyncReadExt,
+    sync::mpsc,
+    task::{self, JoinHandle},
+};
 
 enum AtomicSwapFallback {
     Atomic,
@@ -38,44 +51,448 @@ async fn atomic_swap_with_fallback(src: &Path, target: &Path) -> Result<AtomicSw
     }
 }
 
-pub async fn pkg<R: Read>(tar: R, config: &Config) -> Result<()> {
-    let decoder = Decoder::new(tar)?;
-    let mut tar = tar::Archive::new(decoder);
-    let new_install_path = &config.new_intsall_path;
-    let tmp =
-        tempfile::tempdir_in(&config.cache_path).context("Failed to create temporary directory")?;
-    let prepare_path = tmp.path();
+/// Number of chunks buffered between the download and the unpacker
+const CHANNEL_SIZE: usize = 16;
 
-    info!("Extracting to {:?}...", prepare_path);
-    tar.unpack(prepare_path)
-        .context("Failed to extract mts-linkchats")?;
+/// Prefix of the temporary directories archives are unpacked into
+const TEMP_PREFIX: &str = ".mts-linkchats-unpack-";
 
-    debug!("Creating new install directory is not exists");
-    fs::create_dir_all(&new_install_path)
-        .await
-        .context("Failed to create new install directory")?;
+/// Directories left behind by a launcher that was killed while unpacking are removed after this
+const STALE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 
-    if let Some(entry) = fs::read_dir(prepare_path)
-        .await
-        .context("Failed read prepare directory")?
-        .next_entry()
-        .await
-        .ok()
-        .flatten()
+/// Removes temporary directories of unpackers that never finished
+fn remove_stale(parent: &Path) {
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+            && entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > STALE_AGE);
+        if stale {
+            debug!("Removing stale temporary directory {:?}", entry.path());
+            if let Err(err) = std::fs::remove_dir_all(entry.path()) {
+                warn!("Failed to remove {:?}: {err:#}", entry.path());
+            }
+        }
+    }
+}
+
+/// Turns the chunks sent by an [`Unpacker`] back into a byte stream
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.rx.blocking_recv() {
+                Some(buf) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Version and location of the app found while unpacking
+struct Contents {
+    /// Only known right away for a versioned top level directory or a package
+    version: Option<(Version, VersionSource)>,
+    /// Directory containing the app, relative to the unpack directory
+    root: PathBuf,
+}
+
+/// Decompresses and unpacks a tarball into `dir`, the app is in its top level directory
+fn unpack_tar<R: Read>(reader: R, dir: &Path, detector: &VersionDetector) -> Result<Contents> {
+    let (_, decoder) = compression::decompress(reader)?;
+    let mut archive = tar::Archive::new(decoder);
+
+    let mut contents = None;
+    for entry in archive
+        .entries()
+        .context("Failed get entries from archive")?
     {
-        atomic_swap_with_fallback(&entry.path(), new_install_path).await?;
+        let mut entry = entry.context("Failed get entry from archive")?;
+        if contents.is_none() {
+            let path = entry.path().context("Failed get entry path from archive")?;
+            let root = pkg::top_level(&path)
+                .context("Failed get top level directory of archive")?
+                .to_path_buf();
+            let version = detector
+                .from_directory(&root)
+                .map(|version| (version, VersionSource::Directory));
+            contents = Some(Contents { version, root });
+        }
+        entry
+            .unpack_in(dir)
+            .context("Failed to extract mts-linkchats")?;
+    }
+
+    // read up to the end, so the sender never waits for a reader that is gone
+    io::copy(&mut archive.into_inner(), &mut io::sink())
+        .context("Failed to read the end of the archive")?;
+    contents.context("Failed to get first entry from archive")
+}
+
+/// Unpacks the `data.tar.*` member of a `.deb` package into `dir`.
+///
+/// The version is taken from the control file, the app is wherever the package puts its executable.
+fn unpack_deb<R: Read>(reader: R, dir: &Path) -> Result<Contents> {
+    let mut ar = deb::ArReader::new(reader)?;
+    let mut version = None;
+    let mut root = None;
+    while let Some(name) = ar.next_member()? {
+        if name.starts_with("control.tar") {
+            let found = Version::from_debian(&deb::read_control(&mut ar)?)?;
+            debug!("Package contains version {:?}", found);
+            version = Some(found);
+        } else if name.starts_with("data.tar") {
+            let (_, decoder) = compression::decompress(&mut ar)?;
+            let mut archive = tar::Archive::new(decoder);
+            for entry in archive
+                .entries()
+                .context("Failed get entries from package")?
+            {
+                let mut entry = entry.context("Failed get entry from package")?;
+                let path = entry
+                    .path()
+                    .context("Failed get entry path from package")?
+                    .into_owned();
+                if path.file_name().is_some_and(|name| name == BIN_APP_NAME) {
+                    root = path.parent().map(|parent| {
+                        parent
+                            .components()
+                            .filter(|component| matches!(component, Component::Normal(_)))
+                            .collect::<PathBuf>()
+                    });
+                }
+                entry
+                    .unpack_in(dir)
+                    .context("Failed to extract mts-linkchats")?;
+            }
+            io::copy(&mut archive.into_inner(), &mut io::sink())
+                .context("Failed to read the end of the package")?;
+        }
+    }
+
+    Ok(Contents {
+        version: Some((
+            version.context("Package has no control file")?,
+            VersionSource::Control,
+        )),
+        root: root.with_context(|| anyhow!("Package does not contain `{}`", BIN_APP_NAME))?,
+    })
+}
+
+/// Unpacks a tarball or `.deb` package from `reader` into `dir`
+fn unpack_stream<R: Read>(reader: R, dir: &Path, detector: &VersionDetector) -> Result<Contents> {
+    let (magic, reader) = compression::peek(reader).context("Failed to read archive")?;
+    if magic.starts_with(deb::AR_MAGIC) {
+        unpack_deb(reader, dir)
     } else {
-        bail!("Failed get first entry in prepare directory");
+        unpack_tar(reader, dir, detector)
+    }
+}
+
+/// An archive that was unpacked into a temporary directory
+#[derive(Debug)]
+pub struct Unpacked {
+    tmp: TempDir,
+    /// Directory containing the app, relative to `tmp`
+    root: PathBuf,
+    pub version: Version,
+    pub version_source: VersionSource,
+    /// Hex encoded sha256 hash of the archive
+    pub sha256: String,
+}
+
+impl Unpacked {
+    /// The directory that is installed, the top level directory of a tarball
+    fn root(&self) -> PathBuf {
+        self.tmp.path().join(&self.root)
+    }
+}
+
+/// Unpacks an archive while it is still being downloaded.
+///
+/// Chunks are hashed and handed to a blocking task that decompresses and unpacks them, so the
+/// archive is only decoded once and never has to be held in memory.
+pub struct Unpacker {
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    handle: Option<JoinHandle<Result<Contents>>>,
+    tmp: Option<TempDir>,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl Unpacker {
+    /// Starts unpacking into a new temporary directory inside `parent`
+    pub fn new(parent: &Path, detector: &VersionDetector) -> Result<Self> {
+        remove_stale(parent);
+        let tmp = tempfile::Builder::new()
+            .prefix(TEMP_PREFIX)
+            .tempdir_in(parent)
+            .context("Failed to create temporary directory")?;
+        info!("Extracting to {:?}...", tmp.path());
+
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let dir = tmp.path().to_path_buf();
+        let detector = detector.clone();
+        let handle = task::spawn_blocking(move || {
+            let reader = ChannelReader {
+                rx,
+                buf: Vec::new(),
+                pos: 0,
+            };
+            unpack_stream(reader, &dir, &detector)
+        });
+
+        Ok(Self {
+            tx: Some(tx),
+            handle: Some(handle),
+            tmp: Some(tmp),
+            hasher: Sha256::new(),
+            len: 0,
+        })
+    }
+
+    /// Number of bytes fed so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    async fn join(&mut self) -> Result<Contents> {
+        let handle = self
+            .handle
+            .take()
+            .context("Unpacker has already finished")?;
+        handle.await?
+    }
+
+    pub async fn feed(&mut self, chunk: &[u8]) -> Result<()> {
+        self.hasher.update(chunk);
+        self.len += chunk.len() as u64;
+
+        let tx = self.tx.as_ref().context("Unpacker has already finished")?;
+        if tx.send(chunk.to_vec()).await.is_err() {
+            // the unpacker only stops early if it failed
+            self.tx = None;
+            self.join().await?;
+            bail!("Archive ended unexpectedly");
+        }
+        Ok(())
     }
 
-    if config.install_path != *new_install_path
-        && let AtomicSwapFallback::Atomic =
-            atomic_swap_with_fallback(new_install_path, &config.install_path).await?
+    /// Feeds the first `len` bytes of a file, e.g. a partial download that is resumed
+    pub async fn feed_file(&mut self, path: &Path, len: u64) -> Result<()> {
+        let file = fs::File::open(path)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let mut file = file.take(len);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file
+                .read(&mut buf)
+                .await
+                .with_context(|| anyhow!("Failed to read {:?}", path))?;
+            if n == 0 {
+                break;
+            }
+            self.feed(&buf[..n]).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the unpacker to reach the end of the archive.
+    ///
+    /// If the top level directory has no version in it, it is taken from `file_name`, the name
+    /// the archive was downloaded as, or from the metadata of the unpacked app.
+    pub async fn finish(mut self, file_name: Option<&str>) -> Result<Unpacked> {
+        self.tx = None;
+        let contents = self.join().await?;
+        let tmp = self.tmp.take().context("Unpacker has already finished")?;
+
+        let found = match contents.version {
+            Some(found) => Some(found),
+            None => match file_name.and_then(VersionDetector::from_file_name) {
+                Some(version) => Some((version, VersionSource::FileName)),
+                None => {
+                    let root = tmp.path().join(&contents.root);
+                    task::spawn_blocking(move || VersionDetector::from_metadata(&root)).await??
+                }
+            },
+        };
+        let (version, version_source) =
+            found.context("Failed to detect the version of the archive")?;
+        info!("Detected version {} from the {}", version, version_source);
+
+        Ok(Unpacked {
+            tmp,
+            root: contents.root,
+            version,
+            version_source,
+            sha256: format!("{:x}", self.hasher.finalize_reset()),
+        })
+    }
+
+    /// Unpacks a local archive into a new temporary directory inside `parent`
+    pub async fn unpack_file(
+        path: &Path,
+        parent: &Path,
+        detector: &VersionDetector,
+    ) -> Result<Unpacked> {
+        let len = fs::metadata(path)
+            .await
+            .with_context(|| anyhow!("Failed to open {:?}", path))?
+            .len();
+        let mut unpacker = Self::new(parent, detector)?;
+        unpacker.feed_file(path, len).await?;
+        let file_name = path.file_name().map(|name| name.to_string_lossy());
+        unpacker.finish(file_name.as_deref()).await
+    }
+}
+
+/// Swaps `src` into the install directory and removes the previous installation
+async fn replace_install(src: &Path, config: &Config) -> Result<()> {
+    if let AtomicSwapFallback::Atomic = atomic_swap_with_fallback(src, &config.install_path).await?
     {
         debug!("Removing old directory...");
-        if let Err(err) = fs::remove_dir_all(new_install_path).await {
+        if let Err(err) = fs::remove_dir_all(src).await {
             warn!("Failed to delete old directory: {:#}", err);
         }
     }
     Ok(())
 }
+
+pub async fn pkg(unpacked: Unpacked, config: &Config) -> Result<()> {
+    let new_install_path = &config.new_intsall_path;
+    fs::create_dir_all(new_install_path)
+        .await
+        .context("Failed to create new install directory")?;
+    atomic_swap_with_fallback(&unpacked.root(), new_install_path).await?;
+
+    if config.install_path != *new_install_path {
+        replace_install(new_install_path, config).await?;
+    }
+    Ok(())
+}
+
+/// Moves the unpacked archive next to the installation, to be moved in place by [`apply_staged`]
+pub async fn stage(unpacked: Unpacked, config: &Config) -> Result<()> {
+    fs::create_dir_all(&config.staged_path)
+        .await
+        .context("Failed to create staging directory")?;
+    atomic_swap_with_fallback(&unpacked.root(), &config.staged_path).await?;
+    Ok(())
+}
+
+/// Moves an update extracted by [`stage`] in place
+pub async fn apply_staged(config: &Config) -> Result<()> {
+    replace_install(&config.staged_path, config).await
+}
+
+/// Removes an update extracted by [`stage`] that is not going to be applied
+pub async fn discard_staged(config: &Config) -> Result<()> {
+    match fs::remove_dir_all(&config.staged_path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(Error::new(err).context(anyhow!(
+            "Failed to delete staged update {:?}",
+            config.staged_path
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(version: &str) -> Result<Vec<u8>> {
+        let dir = format!("linkchats-desktop-{version}/");
+        let tar = pkg::tests::tar(&[(&dir, b""), (&format!("{dir}mtslink.bin"), b"hello")])?;
+
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+        io::Write::write_all(&mut encoder, &tar)?;
+        Ok(encoder.finish().into_result()?)
+    }
+
+    #[tokio::test]
+    async fn test_unpack_chunks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = archive("1.2.3")?;
+
+        let mut unpacker = Unpacker::new(dir.path(), &VersionDetector::default())?;
+        for chunk in data.chunks(7) {
+            unpacker.feed(chunk).await?;
+        }
+        assert_eq!(unpacker.len(), data.len() as u64);
+        let unpacked = unpacker.finish(None).await?;
+
+        assert_eq!(unpacked.version.to_string(), "1.2.3");
+        assert_eq!(unpacked.sha256, format!("{:x}", Sha256::digest(&data)));
+        assert_eq!(
+            unpacked.root(),
+            unpacked.tmp.path().join("linkchats-desktop-1.2.3")
+        );
+        assert_eq!(
+            fs::read(unpacked.root().join("mtslink.bin")).await?,
+            b"hello"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unpack_deb() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = deb::tests::package(
+            "1:1.2.3-1",
+            &[
+                ("./usr/share/doc/linkchats/copyright", b"MTS"),
+                ("./opt/linkchats/mtslink.bin", b"hello"),
+            ],
+        )?;
+
+        let mut unpacker = Unpacker::new(dir.path(), &VersionDetector::default())?;
+        for chunk in data.chunks(100) {
+            unpacker.feed(chunk).await?;
+        }
+        let unpacked = unpacker.finish(None).await?;
+
+        assert_eq!(unpacked.version.to_string(), "1:1.2.3-1");
+        assert_eq!(unpacked.root(), unpacked.tmp.path().join("opt/linkchats"));
+        assert_eq!(
+            fs::read(unpacked.root().join("mtslink.bin")).await?,
+            b"hello"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unpack_truncated() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = archive("1.2.3")?;
+
+        let mut unpacker = Unpacker::new(dir.path(), &VersionDetector::default())?;
+        unpacker.feed(&data[..data.len() / 2]).await?;
+        assert!(unpacker.finish(None).await.is_err());
+        Ok(())
+    }
+}
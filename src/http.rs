@@ -1,54 +1,366 @@
+use crate::config::Config;
 use crate::errors::*;
+use crate::tls::{self, PinFailure, PinningError};
 use reqwest::{
-    Response, StatusCode,
-    header::{HeaderMap, HeaderValue, RANGE},
+    Proxy, RequestBuilder, Response, StatusCode, Url,
+    header::{
+        CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, ETAG, HeaderMap, HeaderValue,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+    },
 };
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
 use tokio::time;
 
+/// Returned when the server answers with an unsuccessful http status code
+#[derive(Debug)]
+pub struct StatusError(pub StatusCode);
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unexpected http status code: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Returned when the server answers a conditional request with 304 Not Modified
+#[derive(Debug)]
+pub struct NotModified;
+
+impl fmt::Display for NotModified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Remote object was not modified")
+    }
+}
+
+impl std::error::Error for NotModified {}
+
+/// Decodes `%XX` escapes, `None` if they do not make up valid utf-8
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Takes the file name from a `Content-Disposition` header, preferring the RFC 5987 `filename*`
+fn content_disposition_file_name(value: &str) -> Option<String> {
+    let mut extended = None;
+    let mut plain = None;
+    for param in value.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'encoded name
+                let mut parts = value.trim().splitn(3, '\'');
+                if let (Some(charset), Some(_), Some(encoded)) =
+                    (parts.next(), parts.next(), parts.next())
+                    && charset.eq_ignore_ascii_case("utf-8")
+                {
+                    extended = percent_decode(encoded);
+                }
+            }
+            "filename" => plain = Some(value.trim().trim_matches('"').to_string()),
+            _ => (),
+        }
+    }
+    // only the name is used, never a directory the server made up
+    extended
+        .or(plain)
+        .map(|name| {
+            name.rsplit(['/', '\\'])
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// Fails unless a ranged response starts at `start`, from `Content-Range: bytes 100-199/1234`
+fn check_range_start(resp: &Response, start: u64) -> Result<()> {
+    let sent = resp
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+        .and_then(|value| value.split_once('-'))
+        .and_then(|(sent, _)| sent.trim().parse::<u64>().ok());
+    match sent {
+        Some(sent) if sent == start => Ok(()),
+        Some(sent) => {
+            bail!("Download server sent a range starting at byte {sent} instead of {start}")
+        }
+        None => bail!("Download server sent a range without a valid Content-Range header"),
+    }
+}
+
+/// Response headers that identify a specific version of a remote object
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_length: Option<u64>,
+}
+
+impl Validators {
+    fn from_response(resp: &Response) -> Self {
+        let headers = resp.headers();
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+
+        // for ranged responses the full size is only known from `Content-Range: bytes 0-0/1234`
+        let content_length = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            header(CONTENT_RANGE)
+                .and_then(|value| value.rsplit_once('/').map(|(_, total)| total.parse().ok()))
+                .flatten()
+        } else {
+            // not using `resp.content_length()`, it reports the empty body of HEAD responses
+            header(CONTENT_LENGTH).and_then(|value| value.parse().ok())
+        };
+
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_length,
+        }
+    }
+
+    /// Returns true if the object did not change between two responses, e.g. while it is being
+    /// downloaded in parts.
+    ///
+    /// Unlike [`Validators::matches`], the content length is compared if the server sends neither
+    /// an ETag nor a Last-Modified date, it is the best there is to compare then.
+    pub fn unchanged(&self, other: &Validators) -> bool {
+        if self.etag.is_none() && self.last_modified.is_none() {
+            self.content_length == other.content_length
+        } else {
+            self.matches(other)
+        }
+    }
+
+    /// Returns true if both sides describe the same remote object.
+    ///
+    /// At least an ETag or a Last-Modified date is required, the content length alone is not
+    /// considered strong enough to skip an update.
+    pub fn matches(&self, other: &Validators) -> bool {
+        fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> Option<bool> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a == b),
+                _ => None,
+            }
+        }
+
+        let etag = same(&self.etag, &other.etag);
+        let last_modified = same(&self.last_modified, &other.last_modified);
+        let content_length = same(&self.content_length, &other.content_length);
+
+        if etag.is_none() && last_modified.is_none() {
+            return false;
+        }
+
+        [etag, last_modified, content_length]
+            .into_iter()
+            .flatten()
+            .all(|same| same)
+    }
+}
+
+/// Decides which requests are sent through the configured proxy
+#[derive(Debug, Clone)]
+struct ProxyRules {
+    /// Proxy url without credentials, safe to log
+    url: Url,
+    auth: Option<(String, String)>,
+    no_proxy: Vec<String>,
+}
+
+impl ProxyRules {
+    fn new(config: &Config) -> Result<Option<Self>> {
+        let Some(proxy) = &config.proxy else {
+            return Ok(None);
+        };
+
+        let mut url =
+            Url::parse(proxy).with_context(|| anyhow!("Invalid proxy url {:?}", proxy))?;
+        if !matches!(
+            url.scheme(),
+            "http" | "https" | "socks4" | "socks4a" | "socks5" | "socks5h"
+        ) {
+            bail!("Unsupported proxy scheme {:?}", url.scheme());
+        }
+
+        // credentials may be part of the proxy url, `proxy_auth` takes precedence
+        let mut auth = (!url.username().is_empty()).then(|| {
+            (
+                url.username().to_string(),
+                url.password().unwrap_or_default().to_string(),
+            )
+        });
+        if let Some(proxy_auth) = &config.proxy_auth {
+            let (username, password) = proxy_auth
+                .split_once(':')
+                .context("Proxy credentials must be given as `username:password`")?;
+            auth = Some((username.to_string(), password.to_string()));
+        }
+        url.set_username("").ok();
+        url.set_password(None).ok();
+
+        let no_proxy = config
+            .no_proxy
+            .iter()
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+
+        Ok(Some(ProxyRules {
+            url,
+            auth,
+            no_proxy,
+        }))
+    }
+
+    /// Returns true if the host is excluded from proxying by a `no_proxy` entry.
+    ///
+    /// Entries match the host itself and all of its subdomains, `*` matches every host.
+    fn is_excluded(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches("*.").trim_start_matches('.');
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .strip_suffix(entry)
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+    }
+
+    fn proxy_for(&self, url: &Url) -> Option<&Url> {
+        match url.host_str() {
+            Some(host) if self.is_excluded(host) => None,
+            _ => Some(&self.url),
+        }
+    }
+
+    fn to_proxy(&self) -> Proxy {
+        let rules = self.clone();
+        Proxy::custom(move |url| {
+            rules.proxy_for(url).map(|proxy| {
+                let mut proxy = proxy.clone();
+                if let Some((username, password)) = &rules.auth {
+                    proxy.set_username(username).ok();
+                    proxy.set_password(Some(password)).ok();
+                }
+                proxy
+            })
+        })
+    }
+}
+
 pub struct Client {
     client: reqwest::Client,
     timeout: Option<Duration>,
+    proxy: Option<ProxyRules>,
+    pin_failure: Option<PinFailure>,
 }
 
 impl Client {
-    pub fn new(timeout: Option<u64>) -> Result<Client> {
-        let client = reqwest::ClientBuilder::new()
+    pub fn new(config: &Config) -> Result<Client> {
+        let mut builder = reqwest::ClientBuilder::new()
             .user_agent(format!(
                 "mts-linkchats-launcher/{}",
                 env!("CARGO_PKG_VERSION")
             ))
-            .redirect(reqwest::redirect::Policy::limited(8))
-            .build()
-            .context("Failed to create http client")?;
+            .redirect(reqwest::redirect::Policy::limited(8));
 
-        let timeout = match timeout {
+        let proxy = ProxyRules::new(config)?;
+        if let Some(proxy) = &proxy {
+            debug!(
+                "Using proxy {} (authenticated: {}, no_proxy: {:?})",
+                proxy.url,
+                proxy.auth.is_some(),
+                proxy.no_proxy
+            );
+            builder = builder.proxy(proxy.to_proxy());
+        }
+
+        let (builder, pin_failure) = tls::configure(builder, config)?;
+        let client = builder.build().context("Failed to create http client")?;
+
+        let timeout = match config.timeout.and_then(|value| u64::try_from(value).ok()) {
             Some(0) => None,
             Some(secs) => Some(Duration::from_secs(secs)),
             None => Some(Duration::from_secs(30)),
         };
 
-        Ok(Client { client, timeout })
+        Ok(Client {
+            client,
+            timeout,
+            proxy,
+            pin_failure,
+        })
     }
 
-    async fn send_get(&self, url: &str, offset: Option<u64>) -> Result<Response> {
-        let future = async {
-            let mut headers = HeaderMap::new();
-            if let Some(offset) = offset {
-                headers.insert(RANGE, HeaderValue::from_str(&format!("bytes={offset}-"))?);
-            }
+    fn take_pin_failure(&self) -> Option<String> {
+        self.pin_failure.as_ref()?.lock().unwrap().take()
+    }
 
-            let resp = self
-                .client
-                .get(url)
-                .headers(headers)
-                .send()
-                .await
-                .context("Failed to send http request")?;
+    fn log_route(&self, url: &str) {
+        let Some(rules) = &self.proxy else {
+            return;
+        };
+        let Ok(url) = Url::parse(url) else {
+            return;
+        };
+
+        if let Some(proxy) = rules.proxy_for(&url) {
+            info!(
+                "Connecting to {} through proxy {}",
+                url.host_str().unwrap_or_default(),
+                proxy
+            );
+        } else {
+            info!(
+                "Connecting to {} directly (matched no_proxy)",
+                url.host_str().unwrap_or_default()
+            );
+        }
+    }
+
+    async fn send(&self, req: RequestBuilder) -> Result<Response> {
+        let future = async {
+            let resp = match req.send().await {
+                Ok(resp) => resp,
+                Err(err) => {
+                    if let Some(host) = self.take_pin_failure() {
+                        return Err(Error::new(PinningError { host }));
+                    }
+                    return Err(Error::new(err).context("Failed to send http request"));
+                }
+            };
 
             let status = resp.status();
+            if status == StatusCode::NOT_MODIFIED {
+                return Err(Error::new(NotModified));
+            }
             if !status.is_success() {
-                bail!("Unexpected http status code: {:?}", status);
+                return Err(Error::new(StatusError(status)));
             }
 
             Ok(resp)
@@ -62,9 +374,40 @@ impl Client {
         }
     }
 
+    /// Sends a GET request, optionally for the byte range `start..=end` (open ended without `end`).
+    ///
+    /// With `known` validators the request is conditional and fails with [`NotModified`] if the
+    /// remote object is still the same.
+    async fn send_get(
+        &self,
+        url: &str,
+        range: Option<(u64, Option<u64>)>,
+        known: Option<&Validators>,
+    ) -> Result<Response> {
+        self.log_route(url);
+        let mut headers = HeaderMap::new();
+        if let Some((start, end)) = range {
+            let end = end.map(|end| end.to_string()).unwrap_or_default();
+            headers.insert(
+                RANGE,
+                HeaderValue::from_str(&format!("bytes={start}-{end}"))?,
+            );
+        }
+        if let Some(known) = known {
+            if let Some(etag) = &known.etag {
+                headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+            }
+            if let Some(last_modified) = &known.last_modified {
+                headers.insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+            }
+        }
+
+        self.send(self.client.get(url).headers(headers)).await
+    }
+
     pub async fn fetch(&self, url: &str) -> Result<Vec<u8>> {
         debug!("Fetching {:?}...", url);
-        let resp = self.send_get(url, None).await?;
+        let resp = self.send_get(url, None, None).await?;
 
         let body = resp.bytes();
         let body = if let Some(timeout) = self.timeout {
@@ -80,22 +423,79 @@ impl Client {
         Ok(body.to_vec())
     }
 
-    pub async fn fetch_stream(&self, url: &str, offset: Option<u64>) -> Result<Download> {
-        debug!("Downloading {:?}...", url);
-        let resp = self.send_get(url, offset).await?;
+    /// Checks whether the server answers ranged requests for `url`.
+    ///
+    /// Returns the total size and validators of the object if it does. The request is conditional
+    /// on `known`, see [`Client::send_get`].
+    pub async fn probe_ranges(
+        &self,
+        url: &str,
+        known: Option<&Validators>,
+    ) -> Result<Option<(u64, Validators)>> {
+        debug!("Checking if {:?} supports ranged requests...", url);
+        let resp = self.send_get(url, Some((0, Some(0))), known).await?;
+        if resp.status() != StatusCode::PARTIAL_CONTENT {
+            return Ok(None);
+        }
+
+        let validators = Validators::from_response(&resp);
+        Ok(validators.content_length.map(|total| (total, validators)))
+    }
 
-        if offset.is_some() && resp.status() != StatusCode::PARTIAL_CONTENT {
-            bail!("Download server does not support resumption");
+    /// Downloads the byte range `start..=end`, failing if the server sends anything else
+    pub async fn fetch_range(&self, url: &str, start: u64, end: u64) -> Result<Download> {
+        debug!("Downloading bytes {}-{} of {:?}...", start, end, url);
+        let resp = self.send_get(url, Some((start, Some(end))), None).await?;
+
+        if resp.status() != StatusCode::PARTIAL_CONTENT {
+            bail!("Download server ignored the requested range");
         }
+        check_range_start(&resp, start)?;
+
+        let validators = Validators::from_response(&resp);
+        Ok(Download {
+            resp,
+            timeout: self.timeout,
+            progress: start,
+            total: end + 1,
+            validators,
+        })
+    }
 
-        let progress = offset.unwrap_or(0);
+    /// Downloads `url`, starting at `offset`. The request is conditional on `known`, see
+    /// [`Client::send_get`].
+    pub async fn fetch_stream(
+        &self,
+        url: &str,
+        offset: Option<u64>,
+        known: Option<&Validators>,
+    ) -> Result<Download> {
+        debug!("Downloading {:?}...", url);
+        let resp = self
+            .send_get(url, offset.map(|offset| (offset, None)), known)
+            .await?;
+
+        // a server that ignores the range sends the whole body, the caller has to start over
+        let progress = match offset {
+            Some(offset) if resp.status() == StatusCode::PARTIAL_CONTENT => {
+                check_range_start(&resp, offset)?;
+                offset
+            }
+            Some(_) => {
+                warn!("Download server does not support resumption, restarting from scratch");
+                0
+            }
+            None => 0,
+        };
         let total = resp.content_length().unwrap_or(0) + progress;
+        let validators = Validators::from_response(&resp);
 
         Ok(Download {
             resp,
             timeout: self.timeout,
             progress,
             total,
+            validators,
         })
     }
 }
@@ -105,9 +505,27 @@ pub struct Download {
     timeout: Option<Duration>,
     pub progress: u64,
     pub total: u64,
+    pub validators: Validators,
 }
 
 impl Download {
+    /// Name of the downloaded file, from `Content-Disposition` or the url after redirects
+    pub fn file_name(&self) -> Option<String> {
+        self.resp
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(content_disposition_file_name)
+            .or_else(|| {
+                self.resp
+                    .url()
+                    .path_segments()?
+                    .next_back()
+                    .filter(|name| !name.is_empty())
+                    .and_then(percent_decode)
+            })
+    }
+
     pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
         let future = self.resp.chunk();
         let bytes = if let Some(timeout) = self.timeout {
@@ -127,3 +545,238 @@ impl Download {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answers http requests on localhost with the raw response returned by `respond`, which gets
+    /// the request head. Returns the base url of the server.
+    pub(crate) async fn serve<F>(respond: F) -> Result<String>
+    where
+        F: Fn(&str) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let response = respond(&String::from_utf8_lossy(&request));
+                    stream.write_all(&response).await.ok();
+                    stream.shutdown().await.ok();
+                });
+            }
+        });
+        Ok(url)
+    }
+
+    /// Builds a raw http response, the content length is added to `headers`
+    pub(crate) fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            head += &format!("{name}: {value}\r\n");
+        }
+        head += "\r\n";
+        let mut response = head.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Path of a request, e.g. `/dists/stable/Release`
+    pub(crate) fn request_path(request: &str) -> &str {
+        request.split_whitespace().nth(1).unwrap_or_default()
+    }
+
+    /// Value of a request header, the name is matched case-insensitively
+    pub(crate) fn request_header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        request.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    pub(crate) fn client() -> Result<Client> {
+        Client::new(&crate::config::tests::config()?)
+    }
+
+    async fn read_all(dl: &mut Download) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = dl.chunk().await? {
+            data.extend(chunk);
+        }
+        Ok(data)
+    }
+
+    fn proxy_rules(no_proxy: &[&str]) -> ProxyRules {
+        ProxyRules {
+            url: Url::parse("http://proxy.example.com:3128").unwrap(),
+            auth: None,
+            no_proxy: no_proxy.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_no_proxy_matching() {
+        let rules = proxy_rules(&["mirror.corp", ".internal.example.com", "10.0.0.1"]);
+        let proxy_for = |url: &str| rules.proxy_for(&Url::parse(url).unwrap()).is_some();
+
+        assert!(proxy_for("https://apps.webinar.ru/linkchats.tar.gz"));
+        assert!(!proxy_for("https://mirror.corp/linkchats.tar.gz"));
+        assert!(!proxy_for("https://cdn.mirror.corp/linkchats.tar.gz"));
+        assert!(proxy_for("https://notmirror.corp/linkchats.tar.gz"));
+        assert!(!proxy_for(
+            "https://files.internal.example.com/linkchats.tar.gz"
+        ));
+        assert!(!proxy_for("http://10.0.0.1:8080/linkchats.tar.gz"));
+        assert!(proxy_for("http://10.0.0.2:8080/linkchats.tar.gz"));
+    }
+
+    #[test]
+    fn test_content_disposition_file_name() {
+        assert_eq!(
+            content_disposition_file_name(
+                "attachment; filename=\"linkchats-desktop-1.2.3.tar.gz\""
+            )
+            .as_deref(),
+            Some("linkchats-desktop-1.2.3.tar.gz")
+        );
+        assert_eq!(
+            content_disposition_file_name(
+                "attachment; filename=fallback.tar.gz; filename*=UTF-8''linkchats%201.2.3.tar.gz"
+            )
+            .as_deref(),
+            Some("linkchats 1.2.3.tar.gz")
+        );
+        assert_eq!(
+            content_disposition_file_name("attachment; filename=\"../../linkchats-1.2.tar\"")
+                .as_deref(),
+            Some("linkchats-1.2.tar")
+        );
+        assert_eq!(content_disposition_file_name("inline"), None);
+    }
+
+    #[test]
+    fn test_no_proxy_wildcard() {
+        let rules = proxy_rules(&["*"]);
+        assert!(
+            rules
+                .proxy_for(&Url::parse("https://apps.webinar.ru/").unwrap())
+                .is_none()
+        );
+    }
+
+    fn validators(etag: Option<&str>, last_modified: Option<&str>, len: Option<u64>) -> Validators {
+        Validators {
+            etag: etag.map(String::from),
+            last_modified: last_modified.map(String::from),
+            content_length: len,
+        }
+    }
+
+    #[test]
+    fn test_validators_match_etag() {
+        let a = validators(Some("\"abc\""), None, Some(10));
+        assert!(a.matches(&validators(Some("\"abc\""), None, Some(10))));
+        assert!(!a.matches(&validators(Some("\"def\""), None, Some(10))));
+        assert!(!a.matches(&validators(Some("\"abc\""), None, Some(11))));
+    }
+
+    #[test]
+    fn test_validators_match_last_modified() {
+        let a = validators(None, Some("Mon, 01 Jan 2024 00:00:00 GMT"), None);
+        assert!(a.matches(&validators(
+            Some("\"abc\""),
+            Some("Mon, 01 Jan 2024 00:00:00 GMT"),
+            Some(10)
+        )));
+        assert!(!a.matches(&validators(
+            None,
+            Some("Tue, 02 Jan 2024 00:00:00 GMT"),
+            None
+        )));
+    }
+
+    #[test]
+    fn test_validators_content_length_only_never_matches() {
+        let a = validators(None, None, Some(10));
+        assert!(!a.matches(&a.clone()));
+        assert!(!Validators::default().matches(&Validators::default()));
+    }
+
+    #[test]
+    fn test_validators_unchanged() {
+        let a = validators(None, None, Some(10));
+        assert!(a.unchanged(&a.clone()));
+        assert!(!a.unchanged(&validators(None, None, Some(11))));
+
+        let b = validators(Some("\"abc\""), None, Some(10));
+        assert!(b.unchanged(&b.clone()));
+        assert!(!b.unchanged(&validators(Some("\"def\""), None, Some(10))));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stream_resume() -> Result<()> {
+        const BODY: &[u8] = b"0123456789";
+        let url = serve(|request| match request_path(request) {
+            // ignores the range and sends everything
+            "/full" => response("200 OK", &[], BODY),
+            "/wrong" => response(
+                "206 Partial Content",
+                &[("Content-Range", "bytes 0-9/10")],
+                BODY,
+            ),
+            _ => {
+                let range = request_header(request, "range").unwrap_or_default();
+                let start = range.trim_start_matches("bytes=").trim_end_matches('-');
+                let start = start.parse::<usize>().unwrap();
+                let content_range = format!("bytes {start}-9/10");
+                response(
+                    "206 Partial Content",
+                    &[("Content-Range", &content_range)],
+                    &BODY[start..],
+                )
+            }
+        })
+        .await?;
+        let client = client()?;
+
+        let mut dl = client
+            .fetch_stream(&format!("{url}/resume"), Some(4), None)
+            .await?;
+        assert_eq!((dl.progress, dl.total), (4, 10));
+        assert_eq!(read_all(&mut dl).await?, b"456789");
+        assert_eq!(dl.progress, 10);
+
+        let mut dl = client
+            .fetch_stream(&format!("{url}/full"), Some(4), None)
+            .await?;
+        assert_eq!((dl.progress, dl.total), (0, 10));
+        assert_eq!(read_all(&mut dl).await?, BODY);
+
+        let err = client
+            .fetch_stream(&format!("{url}/wrong"), Some(4), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("starting at byte 0 instead of 4"));
+        Ok(())
+    }
+}
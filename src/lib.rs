@@ -1,11 +1,25 @@
 pub mod apt;
 pub mod args;
+pub mod asar;
+pub mod cache;
+pub mod compression;
 pub mod config;
+pub mod deb;
 pub mod errors;
 pub mod extract;
 pub mod http;
+pub mod offline;
 pub mod paths;
 pub mod pkg;
 pub mod progress;
+pub mod ratelimit;
+pub mod retry;
+pub mod signature;
 pub mod state;
+pub mod timer;
+pub mod tls;
 pub mod ui;
+pub mod units;
+pub mod update;
+pub mod verify;
+pub mod version;
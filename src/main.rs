@@ -2,79 +2,68 @@ use clap::Parser;
 use env_logger::Env;
 use mts_linkchats_launcher::{
     apt::Client,
-    args::Args,
+    args::{Args, SubCommand},
+    asar,
     config::{BIN_APP_NAME, Config},
     errors::*,
-    extract, pkg,
-    state::{State, StateFile},
-    ui,
+    state::StateFile,
+    timer, ui,
+    update::{apply_after_exit, apply_staged, check_installed, should_update, update, update_now},
 };
-use std::time::{Duration, SystemTime};
 use tokio::{fs, process::Command, signal};
 
-async fn should_update(config: &Config, state: &State) -> Result<bool> {
-    if config.force_check_update {
-        Ok(true)
-    } else if !config.check_update {
-        Ok(false)
-    } else {
-        if state.get_pid().is_some() {
-            return Ok(false);
-        }
-
-        let Ok(since_update) = SystemTime::now().duration_since(state.last_update_check) else {
-            // if the last update time is somehow in the future, check for updates now
-            return Ok(true);
-        };
-
-        let hours_since = since_update.as_secs() / 3600;
-        let days_since = hours_since / 24;
-        let hours_since = hours_since % 24;
-
-        debug!(
-            "Last update check was {} days and {} hours ago",
-            days_since, hours_since
-        );
-        let interval: u64 = config.check_update_interval.try_into()?;
-        Ok(since_update >= Duration::from_secs(interval))
+async fn print_tar_url(config: &Config) -> Result<()> {
+    for url in Client::new(config)?.source_urls().await? {
+        println!("{url}");
     }
+    Ok(())
 }
 
-fn print_tar_url() {
-    println!("{}", pkg::DOWNLOAD_URL);
-}
-
-async fn update(config: &Config, state_file: &mut StateFile) -> Result<()> {
-    let tar = if let Some(tar_path) = &config.tar_path {
-        fs::read(tar_path)
-            .await
-            .with_context(|| anyhow!("Failed to read .tar.gz file from {:?}", tar_path))?
-    } else {
-        Client::new(config.timeout.and_then(|value| value.try_into().ok()))?
-            .download_tar(config.download_attempts)
-            .await?
-    };
+/// Prints the installed version and what the installed app says about itself
+async fn status(config: &Config) -> Result<()> {
+    let state_file = StateFile::load(&config.state_path).await?;
+    let state = &state_file.state;
 
-    let version = pkg::parse_version(tar.as_slice())?;
-    let state = &mut state_file.state;
-
-    state.last_update_check = SystemTime::now();
-    if state.version != version {
-        info!("Version not compared. Updating...");
-        state.version = version;
-        extract::pkg(tar.as_slice(), config).await?;
-    } else if config.force_check_update {
-        info!("Latest version is already installed, but --tar options is passed. Force update...");
-        extract::pkg(tar.as_slice(), config).await?;
-    } else {
-        info!("Latest version is already installed, skip...");
+    println!("Install directory: {}", config.install_path.display());
+    match &state.version {
+        Some(version) => println!("Installed version: {version}"),
+        None => println!("Installed version: none"),
+    }
+    if let Some(staged) = &state.staged {
+        println!("Staged update: {}", staged.version);
+    }
+    println!(
+        "Running: {}",
+        if state.get_pid().is_some() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+
+    match asar::installed_package(&config.install_path).await? {
+        Some(package) => {
+            let unknown = || "unknown".to_string();
+            println!("App name: {}", package.name.clone().unwrap_or_else(unknown));
+            println!(
+                "Product name: {}",
+                package.product_name.clone().unwrap_or_else(unknown)
+            );
+            println!("App version: {}", package.version);
+            match check_installed(state, &package) {
+                Ok(()) => println!("Version check: ok"),
+                Err(err) => println!("Version check: {err:#}"),
+            }
+        }
+        None => println!("App metadata: no {} found", asar::APP_ASAR),
     }
-    state_file.save().await?;
-
     Ok(())
 }
 
-async fn start(args: &Args, config: &Config, state_file: &mut StateFile) -> Result<()> {
+/// Launches the installed version and waits for it to exit.
+///
+/// Returns true if the app was supervised until it exited on its own.
+async fn start(args: &Args, config: &Config, already_running: bool) -> Result<bool> {
     let bin = config.install_path.join(BIN_APP_NAME);
 
     let exec_args = ["echo".into(), "--no-sandbox".into()]
@@ -85,6 +74,7 @@ async fn start(args: &Args, config: &Config, state_file: &mut StateFile) -> Resu
 
     debug!("Assembled command: {} {:?}", bin.display(), exec_args);
 
+    let mut exited = false;
     if args.no_exec {
         info!("Skipping exec because --no-exec was used");
     } else {
@@ -110,7 +100,7 @@ async fn start(args: &Args, config: &Config, state_file: &mut StateFile) -> Resu
                 .with_context(|| anyhow!("Failed set permissions in stub desktop file"))?;
         }
 
-        if state_file.state.get_pid().is_some() {
+        if already_running {
             debug!("`{}` already running, rerun without spawn", BIN_APP_NAME);
 
             command
@@ -136,12 +126,13 @@ async fn start(args: &Args, config: &Config, state_file: &mut StateFile) -> Resu
                 value = child.wait() => {
                     let status_code = value.with_context(|| anyhow!("Failed wait `{}`", BIN_APP_NAME))?;
                     debug!("`{}` is exited with code {status_code:?}", BIN_APP_NAME);
+                    exited = true;
                 }
             };
         }
     }
 
-    Ok(())
+    Ok(exited)
 }
 
 #[tokio::main]
@@ -160,20 +151,61 @@ async fn main() -> Result<()> {
 
     debug!("Using install path: {:?}", config.install_path);
 
-    if args.print_tar_url {
-        print_tar_url();
+    if let Some(command) = &args.command {
+        match command {
+            SubCommand::Update => update_now(&config).await?,
+            SubCommand::InstallTimer => timer::install(&config).await?,
+            SubCommand::UninstallTimer => timer::uninstall(&config).await?,
+            SubCommand::Status => status(&config).await?,
+        }
+    } else if args.print_tar_url {
+        print_tar_url(&config).await?;
     } else {
         let mut state_file = StateFile::load(&config.state_path).await?;
+        let running = state_file.state.get_pid().is_some();
 
-        if should_update(&config, &state_file.state).await? {
-            if let Err(err) = update(&config, &mut state_file).await {
-                error!("Update failed: {err:#}");
-                ui::error(&err).await?;
+        // never swap the installation under a running app
+        if !running && let Err(err) = apply_staged(&config, &mut state_file).await {
+            error!("Failed to apply staged update: {err:#}");
+        }
+
+        // without an installation there is nothing to launch in the meantime
+        let background = config.background_update
+            && config.tar_path.is_none()
+            && state_file.state.version.is_some();
+
+        let update_needed = should_update(&config, &state_file.state).await?;
+        if !update_needed {
+            info!("No update needed");
+        }
+
+        let exited = if update_needed && background {
+            info!("Launching the installed version, updating in the background...");
+            let (updated, started) = tokio::join!(
+                update(&config, &mut state_file, true),
+                start(&args, &config, running)
+            );
+            if let Err(err) = updated {
+                error!("Background update failed: {err:#}");
             }
+            started?
         } else {
-            info!("No update needed");
+            if update_needed && let Err(err) = update(&config, &mut state_file, false).await {
+                error!("Update failed: {err:#}");
+                // nobody can do anything about it without a network, keep quiet
+                if !config.offline {
+                    ui::error(&err).await?;
+                }
+            }
+            start(&args, &config, running).await?
+        };
+
+        if exited
+            && let Err(err) =
+                apply_after_exit(&config, async || start(&args, &config, false).await).await
+        {
+            error!("Failed to apply staged update: {err:#}");
         }
-        start(&args, &config, &mut state_file).await?;
     }
 
     Ok(())
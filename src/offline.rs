@@ -0,0 +1,85 @@
+use crate::cache::ArchiveCache;
+use crate::compression;
+use crate::config::Config;
+use crate::errors::*;
+use crate::pkg::{self, VersionDetector};
+use crate::version::Version;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, task};
+
+async fn read_version(path: &Path, detector: &VersionDetector) -> Result<Version> {
+    let path = path.to_path_buf();
+    let detector = detector.clone();
+    task::spawn_blocking(move || {
+        let file = File::open(&path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let file_name = path.file_name().map(|name| name.to_string_lossy());
+        let (version, source) =
+            pkg::parse_version(BufReader::new(file), file_name.as_deref(), &detector)?;
+        debug!(
+            "Detected version {} of {:?} from the {}",
+            version, path, source
+        );
+        Ok(version)
+    })
+    .await?
+}
+
+/// Lists the archives in `dir` with the version they contain, skipping anything unreadable
+pub async fn scan_dir(dir: &Path, detector: &VersionDetector) -> Result<Vec<(PathBuf, Version)>> {
+    let mut dir = fs::read_dir(dir)
+        .await
+        .with_context(|| anyhow!("Failed to read archive directory {:?}", dir))?;
+
+    let mut archives = Vec::new();
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !compression::EXTENSIONS
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            continue;
+        }
+
+        match read_version(&path, detector).await {
+            Ok(version) => archives.push((path, version)),
+            Err(err) => warn!("Ignoring archive {:?}: {err:#}", path),
+        }
+    }
+    Ok(archives)
+}
+
+/// Finds the newest archive in the archive cache and the configured archive directory.
+///
+/// Returns `None` if there is no archive newer than the `installed` version. With a pinned
+/// version only an archive of that version is returned, even if it is older.
+pub async fn find_archive(
+    config: &Config,
+    installed: Option<&Version>,
+) -> Result<Option<(PathBuf, Version)>> {
+    let mut archives = ArchiveCache::new(config)
+        .list()
+        .await?
+        .into_iter()
+        .map(|archive| (archive.path, archive.version))
+        .collect::<Vec<_>>();
+    // the directory may well be on a drive that is not mounted right now
+    if let Some(dir) = &config.offline_archive_dir {
+        match scan_dir(dir, &VersionDetector::new(config)).await {
+            Ok(found) => archives.extend(found),
+            Err(err) => warn!("{err:#}"),
+        }
+    }
+    debug!("Archives available offline: {:?}", archives);
+
+    if let Some(pinned) = &config.pin_version {
+        return Ok(archives.into_iter().find(|(_, version)| version == pinned));
+    }
+    let newest = archives.into_iter().max_by(|(_, a), (_, b)| a.cmp(b));
+    Ok(newest.filter(|(_, version)| installed.is_none_or(|installed| version > installed)))
+}
@@ -5,8 +5,12 @@ use std::path::PathBuf;
 pub struct Paths {
     pub install: PathBuf,
     pub new_install: PathBuf,
+    pub staged: PathBuf,
     pub state: PathBuf,
     pub cache: PathBuf,
+    pub download: PathBuf,
+    pub archives: PathBuf,
+    pub systemd_units: PathBuf,
 }
 
 impl Paths {
@@ -15,12 +19,17 @@ impl Paths {
             .context("Failed to detect data directory")?
             .join("mts-linkchats-launcher");
         let cache_dir = dirs::cache_dir().context("Failed to detect cache directory")?;
+        let config_dir = dirs::config_dir().context("Failed to detect config directory")?;
 
         Ok(Self {
             install: data_dir.join("install"),
             new_install: data_dir.join("install-new"),
+            staged: data_dir.join("install-staged"),
             state: data_dir.join("state.toml"),
+            download: cache_dir.join("mts-linkchats-launcher"),
+            archives: cache_dir.join("mts-linkchats-launcher").join("archives"),
             cache: cache_dir,
+            systemd_units: config_dir.join("systemd/user"),
         })
     }
 }
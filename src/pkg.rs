@@ -1,31 +1,309 @@
+use crate::asar;
+use crate::compression;
+use crate::config::Config;
+use crate::deb;
 use crate::errors::*;
-use libflate::gzip::Decoder;
-use std::io::Read;
+use crate::version::Version;
+use regex::Regex;
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    iter,
+    path::{Component, Path},
+    sync::LazyLock,
+};
 use tar::Archive;
 
 pub const DOWNLOAD_URL: &str = "https://apps.webinar.ru/weteams/linkchats-desktop.tar.gz";
+/// Name of the package in a Debian repository
+pub const DEB_PACKAGE: &str = "linkchats-desktop";
 
-pub fn parse_version<R: Read>(data: R) -> Result<String> {
-    let archive = Decoder::new(data).context("Failed to decode tar archive")?;
-    let mut archive = Archive::new(archive);
+/// Matches the version at the end of a name like `linkchats-desktop-1.3.0-beta.1`, used for the
+/// top level directory unless `version_regex` is configured and always used for file names
+pub const VERSION_REGEX: &str =
+    r"[-_]v?(?P<version>\d+(?:\.\d+)+(?:[-~][0-9A-Za-z.~-]+)?(?:\+[0-9A-Za-z.-]+)?)$";
+
+/// File with just the version in it, relative to the app directory
+const VERSION_FILE: &str = "version";
+
+static FILE_NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(VERSION_REGEX).unwrap());
+
+/// Where the version of an archive was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionSource {
+    Directory,
+    FileName,
+    AppAsar,
+    VersionFile,
+    Control,
+}
+
+impl fmt::Display for VersionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VersionSource::Directory => "top level directory",
+            VersionSource::FileName => "file name",
+            VersionSource::AppAsar => "package.json in app.asar",
+            VersionSource::VersionFile => "version file",
+            VersionSource::Control => "package control file",
+        })
+    }
+}
+
+/// Finds the version of an archive.
+///
+/// Tries the name of the top level directory first, then the file name of the archive and
+/// finally the metadata of the app inside it.
+#[derive(Debug, Clone)]
+pub struct VersionDetector {
+    directory: Regex,
+}
+
+impl Default for VersionDetector {
+    fn default() -> Self {
+        Self {
+            directory: FILE_NAME_REGEX.clone(),
+        }
+    }
+}
+
+/// Takes the version from the `version` group of `regex`, the first group or the whole match
+fn capture_version(regex: &Regex, text: &str) -> Option<Version> {
+    let captures = regex.captures(text)?;
+    let found = captures
+        .name("version")
+        .or_else(|| captures.get(1))
+        .or_else(|| captures.get(0))?;
+    found.as_str().parse().ok()
+}
+
+/// First normal component of a path inside an archive, e.g. `linkchats-desktop-1.2.3`
+pub fn top_level(path: &Path) -> Option<&Path> {
+    path.components().find_map(|component| match component {
+        Component::Normal(name) => Some(Path::new(name)),
+        _ => None,
+    })
+}
+
+impl VersionDetector {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            directory: config.version_regex.clone(),
+        }
+    }
+
+    pub fn from_directory(&self, dir: &Path) -> Option<Version> {
+        capture_version(&self.directory, &dir.to_string_lossy())
+    }
+
+    /// Takes the version from the file name of an archive, e.g. `linkchats-desktop-1.2.3.tar.gz`
+    pub fn from_file_name(name: &str) -> Option<Version> {
+        let name = name.rsplit('/').next().unwrap_or(name);
+        let stem = compression::EXTENSIONS
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+            .unwrap_or(name);
+        capture_version(&FILE_NAME_REGEX, stem)
+    }
+
+    /// Reads the version from a file of the app, `path` is relative to the app directory.
+    ///
+    /// Returns `None` for files that do not contain the version.
+    pub fn from_entry<R: Read>(path: &Path, reader: R) -> Result<Option<(Version, VersionSource)>> {
+        if path == Path::new(asar::APP_ASAR) {
+            let package = asar::read_package_json(reader)
+                .with_context(|| anyhow!("Failed to read {:?}", path))?;
+            debug!("Found package.json in {:?}: {:?}", path, package);
+            Ok(Some((package.version.parse()?, VersionSource::AppAsar)))
+        } else if path == Path::new(VERSION_FILE) {
+            let mut version = String::new();
+            reader
+                .take(1024)
+                .read_to_string(&mut version)
+                .with_context(|| anyhow!("Failed to read {:?}", path))?;
+            Ok(Some((version.parse()?, VersionSource::VersionFile)))
+        } else {
+            Ok(None)
+        }
+    }
 
-    if let Some(entry) = archive
+    /// Reads the version from the metadata of an unpacked app in `dir`
+    pub fn from_metadata(dir: &Path) -> Result<Option<(Version, VersionSource)>> {
+        let archive = dir.join(asar::APP_ASAR);
+        if archive.exists() {
+            let package = asar::read_package_json_at(&archive)
+                .with_context(|| anyhow!("Failed to read {:?}", archive))?;
+            debug!("Found package.json in {:?}: {:?}", archive, package);
+            return Ok(Some((package.version.parse()?, VersionSource::AppAsar)));
+        }
+
+        let path = dir.join(VERSION_FILE);
+        match File::open(&path) {
+            Ok(file) => Self::from_entry(Path::new(VERSION_FILE), file),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::new(err).context(anyhow!("Failed to open {:?}", path))),
+        }
+    }
+}
+
+/// Reads the version of an archive without unpacking it, `file_name` is the name it was
+/// downloaded as
+pub fn parse_version<R: Read>(
+    data: R,
+    file_name: Option<&str>,
+    detector: &VersionDetector,
+) -> Result<(Version, VersionSource)> {
+    let (magic, data) = compression::peek(data).context("Failed to read archive")?;
+    if magic.starts_with(deb::AR_MAGIC) {
+        let version = Version::from_debian(&deb::read_version(data)?)?;
+        return Ok((version, VersionSource::Control));
+    }
+
+    let (_, archive) = compression::decompress(data)?;
+    let mut archive = Archive::new(archive);
+    let mut entries = archive
         .entries()
-        .context("Failed get entries from archive")?
+        .context("Failed get entries from archive")?;
+
+    let first = entries
         .next()
-    {
+        .context("Failed to get first entry from archive")?
+        .context("Failed get entry from archive")?;
+    let dir = top_level(&first.path().context("Failed get entry path from archive")?)
+        .context("Failed get top level directory of archive")?
+        .to_path_buf();
+    if let Some(version) = detector.from_directory(&dir) {
+        return Ok((version, VersionSource::Directory));
+    }
+    if let Some(version) = file_name.and_then(VersionDetector::from_file_name) {
+        return Ok((version, VersionSource::FileName));
+    }
+
+    // app.asar wins over a version file, like it does for an unpacked archive
+    let mut found = None;
+    for entry in iter::once(Ok(first)).chain(entries) {
         let entry = entry.context("Failed get entry from archive")?;
-        let path = entry.path().context("Failed get entry path from archive")?;
-        let path = path.to_string_lossy();
+        let path = entry
+            .path()
+            .context("Failed get entry path from archive")?
+            .into_owned();
+        let Ok(path) = path.strip_prefix(&dir) else {
+            continue;
+        };
+        match VersionDetector::from_entry(path, entry)? {
+            Some((version, VersionSource::AppAsar)) => {
+                return Ok((version, VersionSource::AppAsar));
+            }
+            Some(version_file) => found = Some(version_file),
+            None => (),
+        }
+    }
+    found.context("Failed to detect the version of the archive")
+}
 
-        if let Some(version) = path.split("-").nth(2)
-            && version.split(".").count() == 3
-        {
-            Ok(version.to_owned())
-        } else {
-            bail!("Failed to get version from first entry archive");
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds an uncompressed tarball, paths ending with a slash are directories
+    pub(crate) fn tar(files: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+            }
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data)?;
         }
-    } else {
-        bail!("Failed to get first entry from archive");
+        Ok(builder.into_inner()?)
+    }
+
+    #[test]
+    fn test_from_directory() -> Result<()> {
+        let detector = VersionDetector::default();
+        let from_directory = |dir: &str| detector.from_directory(Path::new(dir));
+        assert_eq!(
+            from_directory("linkchats-desktop-1.2.3"),
+            Some("1.2.3".parse()?)
+        );
+        assert_eq!(
+            from_directory("linkchats-desktop-1.3.0-beta.1").map(|v| v.to_string()),
+            Some("1.3.0-beta.1".to_string())
+        );
+        assert_eq!(from_directory("linkchats_v2.0"), Some("2.0".parse()?));
+        assert_eq!(from_directory("linkchats-desktop"), None);
+
+        let detector = VersionDetector {
+            directory: Regex::new(r"^MTS Link \((.+)\)$")?,
+        };
+        assert_eq!(
+            detector.from_directory(Path::new("MTS Link (1.4.0)")),
+            Some("1.4.0".parse()?)
+        );
+        assert_eq!(detector.from_directory(Path::new("linkchats-1.4.0")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_file_name() -> Result<()> {
+        assert_eq!(
+            VersionDetector::from_file_name("linkchats-desktop-1.2.3.tar.gz"),
+            Some("1.2.3".parse()?)
+        );
+        assert_eq!(
+            VersionDetector::from_file_name("/downloads/linkchats-1.10.tar.zst"),
+            Some("1.10".parse()?)
+        );
+        assert_eq!(
+            VersionDetector::from_file_name("linkchats-desktop.tar.gz"),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_version() -> Result<()> {
+        let detector = VersionDetector::default();
+        let parse = |data: &[u8], file_name| parse_version(data, file_name, &detector);
+
+        let data = tar(&[("linkchats-desktop-1.2.3/mtslink.bin", b"")])?;
+        assert_eq!(
+            parse(&data, None)?,
+            ("1.2.3".parse()?, VersionSource::Directory)
+        );
+
+        let data = tar(&[
+            ("linkchats/mtslink.bin", b""),
+            ("linkchats/version", b"1.4.0\n"),
+        ])?;
+        assert_eq!(
+            parse(&data, Some("linkchats-1.4.1.tar"))?,
+            ("1.4.1".parse()?, VersionSource::FileName)
+        );
+        assert_eq!(
+            parse(&data, Some("linkchats.tar"))?,
+            ("1.4.0".parse()?, VersionSource::VersionFile)
+        );
+
+        let package = br#"{"name":"linkchats","version":"1.5.0"}"#;
+        let data = tar(&[
+            ("linkchats/version", b"1.4.0"),
+            (
+                "linkchats/resources/app.asar",
+                &asar::tests::asar(&[("package.json", package)]),
+            ),
+        ])?;
+        assert_eq!(
+            parse(&data, None)?,
+            ("1.5.0".parse()?, VersionSource::AppAsar)
+        );
+
+        let data = tar(&[("linkchats/mtslink.bin", b"")])?;
+        assert!(parse(&data, None).is_err());
+        Ok(())
     }
 }
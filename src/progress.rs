@@ -3,7 +3,7 @@ use crate::ui::Zenity;
 use tokio::io::AsyncWriteExt;
 
 pub struct ProgressBar {
-    ui: Zenity,
+    ui: Option<Zenity>,
 }
 
 impl ProgressBar {
@@ -17,11 +17,16 @@ impl ProgressBar {
             "--ok-label",
             "😺",
         ])?;
-        Ok(ProgressBar { ui })
+        Ok(ProgressBar { ui: Some(ui) })
+    }
+
+    /// A progress bar that is not shown, for downloads in the background
+    pub fn hidden() -> ProgressBar {
+        ProgressBar { ui: None }
     }
 
     pub async fn update(&mut self, progress: u64) -> Result<()> {
-        if let Some(stdin) = &mut self.ui.child.stdin {
+        if let Some(stdin) = self.ui.as_mut().and_then(|ui| ui.child.stdin.as_mut()) {
             let buf = format!("{}\n", progress);
             stdin.write_all(buf.as_bytes()).await?;
             stdin.flush().await?;
@@ -29,8 +34,20 @@ impl ProgressBar {
         Ok(())
     }
 
+    /// Replaces the text shown above the progress bar
+    pub async fn set_text(&mut self, text: &str) -> Result<()> {
+        if let Some(stdin) = self.ui.as_mut().and_then(|ui| ui.child.stdin.as_mut()) {
+            let buf = format!("# {}\n", text.replace('\n', " "));
+            stdin.write_all(buf.as_bytes()).await?;
+            stdin.flush().await?;
+        }
+        Ok(())
+    }
+
     pub async fn close(&mut self) -> Result<()> {
-        self.ui.child.kill().await?;
+        if let Some(ui) = &mut self.ui {
+            ui.child.kill().await?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,61 @@
+use crate::errors::*;
+use crate::units;
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting the average download rate, allowing bursts of up to one second
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    available: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        debug!(
+            "Limiting download rate to {}/s",
+            units::format_size(bytes_per_second)
+        );
+        Self {
+            rate: bytes_per_second as f64,
+            available: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Records `bytes` as transferred and returns how long to wait to stay below the rate
+    pub fn consume(&mut self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+
+        self.available = (self.available + elapsed * self.rate).min(self.rate);
+        self.available -= bytes as f64;
+
+        if self.available < 0.0 {
+            Duration::from_secs_f64(-self.available / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_delays() {
+        let mut limiter = RateLimiter::new(1000);
+        let delay = limiter.consume(2000);
+        assert!(delay > Duration::from_millis(1900) && delay <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_rate_limiter_burst_is_capped() {
+        let mut limiter = RateLimiter::new(1000);
+        limiter.last -= Duration::from_secs(60);
+        assert_eq!(limiter.consume(1000), Duration::ZERO);
+        assert!(limiter.consume(1000) > Duration::from_millis(900));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::errors::*;
+use crate::http::StatusError;
+use crate::tls::PinningError;
+use std::{io, time::Duration};
+
+/// How long to wait between download attempts
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    /// Random deviation from the delay, as a fraction of it (0.2 = ±20%)
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            bail!(
+                "Retry multiplier must be at least 1, got {}",
+                self.multiplier
+            );
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            bail!("Retry jitter must be between 0 and 1, got {}", self.jitter);
+        }
+        Ok(())
+    }
+
+    /// Delay before the given retry, without jitter (the first retry is 0)
+    pub fn base_delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    /// Delay before the given retry, with jitter applied
+    pub fn delay(&self, retry: u32) -> Duration {
+        let base = self.base_delay(retry).as_secs_f64();
+        let deviation = base * self.jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_secs_f64((base + deviation).max(0.0))
+    }
+}
+
+fn find_cause<T: std::error::Error + 'static>(err: &Error) -> Option<&T> {
+    err.chain().find_map(|mut cause| {
+        loop {
+            if let Some(err) = cause.downcast_ref::<T>() {
+                return Some(err);
+            }
+            // io errors hide the error they wrap from `source()`, and may be nested
+            cause = cause.downcast_ref::<io::Error>()?.get_ref()?;
+        }
+    })
+}
+
+/// Returns false for errors that are not going to go away by trying again, like a missing file
+/// on the server or a failed certificate check. Unknown errors are assumed to be retryable.
+pub fn is_retryable(err: &Error) -> bool {
+    if find_cause::<PinningError>(err).is_some() {
+        return false;
+    }
+    if find_cause::<rustls::Error>(err).is_some() {
+        return false;
+    }
+    if let Some(StatusError(status)) = find_cause::<StatusError>(err) {
+        return status.is_server_error()
+            || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || *status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn test_backoff_delays() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_secs(1),
+            multiplier: 3.0,
+            max_delay: Duration::from_secs(20),
+            jitter: 0.0,
+        };
+        assert_eq!(policy.base_delay(0), Duration::from_secs(1));
+        assert_eq!(policy.base_delay(1), Duration::from_secs(3));
+        assert_eq!(policy.base_delay(2), Duration::from_secs(9));
+        assert_eq!(policy.base_delay(3), Duration::from_secs(20));
+        assert_eq!(policy.base_delay(u32::MAX), Duration::from_secs(20));
+        assert_eq!(policy.delay(1), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2).as_secs_f64();
+            assert!((2.0..=6.0).contains(&delay), "{delay}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let policy = RetryPolicy {
+            multiplier: 0.5,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        let policy = RetryPolicy {
+            jitter: 1.5,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        assert!(RetryPolicy::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |code| Error::new(StatusError(code)).context("Failed to download");
+        assert!(is_retryable(&status(StatusCode::BAD_GATEWAY)));
+        assert!(is_retryable(&status(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(&status(StatusCode::NOT_FOUND)));
+        assert!(!is_retryable(&status(StatusCode::FORBIDDEN)));
+
+        let pinning = Error::new(PinningError {
+            host: "example.com".to_string(),
+        });
+        assert!(!is_retryable(&pinning));
+
+        let tls = Error::new(io::Error::other(io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer),
+        )));
+        assert!(!is_retryable(&tls));
+
+        let reset = Error::new(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_retryable(&reset));
+        assert!(is_retryable(&anyhow!(
+            "Download timed out due to inactivity"
+        )));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::config::Config;
+use crate::errors::*;
+use minisign_verify::{Error as MinisignError, PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tokio::{fs, process::Command, task};
+
+/// File extension of minisign signatures, appended to the archive url or path
+pub const MINISIGN_EXTENSION: &str = "minisig";
+/// File extension of detached OpenPGP signatures, appended to the archive url or path
+pub const OPENPGP_EXTENSION: &str = "sig";
+
+/// What to do when an archive can not be authenticated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignaturePolicy {
+    /// Refuse to install the archive
+    #[default]
+    Require,
+    /// Log a warning and install the archive anyway
+    Warn,
+}
+
+/// Detached signatures published next to an archive
+#[derive(Debug, Default)]
+pub struct Signatures {
+    pub minisign: Option<String>,
+    pub openpgp: Option<Vec<u8>>,
+}
+
+/// Returns true if any signing keys are configured
+pub fn is_enabled(config: &Config) -> bool {
+    !config.minisign_public_keys.is_empty() || !config.openpgp_keyrings.is_empty()
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+async fn read_optional(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path).await {
+        Ok(buf) => Ok(Some(buf)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Error::new(err).context(anyhow!("Failed to read signature {:?}", path))),
+    }
+}
+
+/// Reads the signatures stored next to a local archive
+pub async fn local(config: &Config, path: &Path) -> Result<Signatures> {
+    read_local(
+        path,
+        !config.minisign_public_keys.is_empty(),
+        !config.openpgp_keyrings.is_empty(),
+    )
+    .await
+}
+
+/// Reads the minisign and OpenPGP signatures stored next to a local archive, as requested
+pub async fn read_local(path: &Path, minisign: bool, openpgp: bool) -> Result<Signatures> {
+    let mut signatures = Signatures::default();
+    if minisign {
+        let path = with_extension(path, MINISIGN_EXTENSION);
+        if let Some(buf) = read_optional(&path).await? {
+            signatures.minisign =
+                Some(String::from_utf8(buf).context("Minisign signature is not valid utf-8")?);
+        }
+    }
+    if openpgp {
+        signatures.openpgp = read_optional(&with_extension(path, OPENPGP_EXTENSION)).await?;
+    }
+    Ok(signatures)
+}
+
+fn verify_minisign(public_keys: &[String], signature: &str, path: &Path) -> Result<()> {
+    let signature = Signature::decode(signature).context("Failed to decode minisign signature")?;
+
+    let mut last_err = None;
+    for key in public_keys {
+        let key = PublicKey::from_base64(key)
+            .with_context(|| anyhow!("Invalid minisign public key {:?}", key))?;
+        let mut verifier = match key.verify_stream(&signature) {
+            Ok(verifier) => verifier,
+            // a key id mismatch means the signature was made by another key
+            Err(MinisignError::UnexpectedKeyId) => continue,
+            // signatures made by `minisign -l` sign the file itself instead of its hash
+            Err(MinisignError::UnsupportedLegacyMode) => {
+                let data =
+                    std::fs::read(path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+                match key.verify(&data, &signature, true) {
+                    Ok(()) => {
+                        info!(
+                            "Archive has a valid legacy minisign signature ({})",
+                            signature.trusted_comment()
+                        );
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        last_err = Some(err);
+                        continue;
+                    }
+                }
+            }
+            Err(err) => {
+                last_err = Some(err);
+                continue;
+            }
+        };
+
+        let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file
+                .read(&mut buf)
+                .with_context(|| anyhow!("Failed to read {:?}", path))?;
+            if n == 0 {
+                break;
+            }
+            verifier.update(&buf[..n]);
+        }
+
+        match verifier.finalize() {
+            Ok(()) => {
+                info!(
+                    "Archive has a valid minisign signature ({})",
+                    signature.trusted_comment()
+                );
+                return Ok(());
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    match last_err {
+        Some(err) => Err(anyhow!("Invalid minisign signature: {err}")),
+        None => bail!("Minisign signature was not made by any of the configured keys"),
+    }
+}
+
+async fn verify_openpgp(keyrings: &[PathBuf], signature: &[u8], path: &Path) -> Result<()> {
+    let sig_file = tempfile::NamedTempFile::new().context("Failed to create temporary file")?;
+    fs::write(sig_file.path(), signature)
+        .await
+        .context("Failed to write signature to temporary file")?;
+
+    let mut command = Command::new("gpgv");
+    for keyring in keyrings {
+        command.arg("--keyring").arg(keyring);
+    }
+    command.arg(sig_file.path()).arg(path);
+    debug!("Running {:?}", command);
+
+    let output = command
+        .output()
+        .await
+        .context("Failed to run gpgv, is gnupg installed?")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("Invalid OpenPGP signature: {}", stderr.trim());
+    }
+
+    info!("Archive has a valid OpenPGP signature");
+    debug!("gpgv: {}", stderr.trim());
+    Ok(())
+}
+
+/// Verifies the signatures of an archive against the configured keys.
+///
+/// Every signature that can be checked has to be valid, and at least one has to be present.
+/// With [`SignaturePolicy::Warn`] failures are logged instead of returned.
+pub async fn verify(config: &Config, path: &Path, signatures: &Signatures) -> Result<()> {
+    if !is_enabled(config) {
+        debug!("No signing keys configured, skipping signature verification");
+        return Ok(());
+    }
+
+    let result = async {
+        let mut verified = false;
+
+        if let Some(signature) = &signatures.minisign
+            && !config.minisign_public_keys.is_empty()
+        {
+            let keys = config.minisign_public_keys.clone();
+            let signature = signature.clone();
+            let path = path.to_path_buf();
+            task::spawn_blocking(move || verify_minisign(&keys, &signature, &path)).await??;
+            verified = true;
+        }
+
+        if let Some(signature) = &signatures.openpgp
+            && !config.openpgp_keyrings.is_empty()
+        {
+            verify_openpgp(&config.openpgp_keyrings, signature, path).await?;
+            verified = true;
+        }
+
+        if !verified {
+            bail!("No signature was found for the archive");
+        }
+        Ok(())
+    }
+    .await;
+
+    match (result, config.signature_policy) {
+        (Ok(()), _) => Ok(()),
+        (Err(err), SignaturePolicy::Require) => Err(err.context("Signature verification failed")),
+        (Err(err), SignaturePolicy::Warn) => {
+            warn!("Signature verification failed, installing anyway: {err:#}");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    fn signed_file(content: &[u8]) -> Result<tempfile::NamedTempFile> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), content)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_minisign_valid() -> Result<()> {
+        let file = signed_file(b"test")?;
+        verify_minisign(&[PUBLIC_KEY.to_string()], SIGNATURE, file.path())
+    }
+
+    #[test]
+    fn test_minisign_tampered() -> Result<()> {
+        let file = signed_file(b"Test")?;
+        assert!(verify_minisign(&[PUBLIC_KEY.to_string()], SIGNATURE, file.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_minisign_legacy() -> Result<()> {
+        const LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==";
+
+        let file = signed_file(b"test")?;
+        verify_minisign(&[PUBLIC_KEY.to_string()], LEGACY_SIGNATURE, file.path())?;
+
+        let file = signed_file(b"Test")?;
+        let err =
+            verify_minisign(&[PUBLIC_KEY.to_string()], LEGACY_SIGNATURE, file.path()).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid minisign signature"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_minisign_unknown_key() -> Result<()> {
+        let file = signed_file(b"test")?;
+        let other_key = "RWTAPRWSAW9hWbQnzNwf0+8dBmSh9Nn0xwmMV6kymFlxiwzwT2xJMU1P";
+        assert!(verify_minisign(&[other_key.to_string()], SIGNATURE, file.path()).is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
-use crate::{config::BIN_APP_NAME, errors::*};
-use serde::{Deserialize, Serialize};
+use crate::{config::BIN_APP_NAME, errors::*, http::Validators, version::Version};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     ffi::OsStr,
     path::{Path, PathBuf},
@@ -11,28 +11,81 @@ use tokio::fs;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
-    pub version: String,
+    /// Installed version, `None` before the first install
+    #[serde(default, deserialize_with = "deserialize_installed")]
+    pub version: Option<Version>,
     pub last_update_check: SystemTime,
-    #[serde(skip)]
+    /// Validators of the remote archive the installed version was taken from
+    #[serde(default)]
+    pub remote: Option<Validators>,
+    /// Update that was extracted in the background and is applied on the next launch
+    #[serde(default)]
+    pub staged: Option<StagedUpdate>,
+    /// Remote archive that was downloaded but refused, it is not downloaded again until it changes
+    #[serde(default)]
+    pub rejected: Option<RejectedUpdate>,
+    #[serde(skip, default = "find_running")]
     pid: LazyLock<Option<Pid>>,
 }
 
+/// An update that was extracted next to the installation, waiting to be moved in place
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedUpdate {
+    pub version: Version,
+    /// Validators of the remote archive the update was taken from
+    #[serde(default)]
+    pub remote: Option<Validators>,
+}
+
+/// A remote archive that was downloaded but not installed, e.g. because it is not the pinned version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedUpdate {
+    pub version: Version,
+    /// Validators of the refused remote archive
+    pub remote: Validators,
+}
+
 impl Default for State {
     fn default() -> Self {
         Self {
             version: Default::default(),
             last_update_check: SystemTime::UNIX_EPOCH,
-            pid: LazyLock::new(|| {
-                let sys = System::new_all();
+            remote: None,
+            staged: None,
+            rejected: None,
+            pid: find_running(),
+        }
+    }
+}
 
-                sys.processes_by_name(OsStr::new(BIN_APP_NAME))
-                    .next()
-                    .map(|process| process.pid())
-            }),
+/// Reads the installed version, older launchers stored an empty string before the first install
+fn deserialize_installed<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Version>, D::Error> {
+    let version = String::deserialize(deserializer)?;
+    if version.is_empty() {
+        return Ok(None);
+    }
+    match Version::from_saved(&version) {
+        Ok(version) => Ok(Some(version)),
+        Err(err) => {
+            warn!("Ignoring invalid installed version in state file: {err:#}");
+            Ok(None)
         }
     }
 }
 
+/// Looks up a running instance of the app the first time it is needed
+fn find_running() -> LazyLock<Option<Pid>> {
+    LazyLock::new(|| {
+        let sys = System::new_all();
+
+        sys.processes_by_name(OsStr::new(BIN_APP_NAME))
+            .next()
+            .map(|process| process.pid())
+    })
+}
+
 impl State {
     pub fn get_pid(&self) -> Option<Pid> {
         *self.pid
@@ -91,3 +144,53 @@ impl StateFile {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed_version() -> Result<()> {
+        let state = toml::from_str::<State>(
+            r#"
+version = "1.2.3/"
+[last_update_check]
+secs_since_epoch = 0
+nanos_since_epoch = 0
+"#,
+        )?;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+
+        let state = toml::from_str::<State>(
+            r#"
+version = ""
+[last_update_check]
+secs_since_epoch = 0
+nanos_since_epoch = 0
+"#,
+        )?;
+        assert_eq!(state.version, None);
+
+        let state = State {
+            version: Some("1.3.0-beta.1".parse()?),
+            ..Default::default()
+        };
+        let state = toml::from_str::<State>(&toml::to_string(&state)?)?;
+        assert_eq!(state.version, Some("1.3.0-beta.1".parse()?));
+
+        // the package revision sorts after the release, also once read back
+        let state = State {
+            version: Some(Version::from_debian("1.2.3-1")?),
+            staged: Some(StagedUpdate {
+                version: "1.2.3".parse()?,
+                remote: None,
+            }),
+            ..Default::default()
+        };
+        let state = toml::from_str::<State>(&toml::to_string(&state)?)?;
+        let installed = state.version.as_ref().unwrap();
+        let staged = &state.staged.as_ref().unwrap().version;
+        assert!(installed > staged);
+        Ok(())
+    }
+}
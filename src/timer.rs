@@ -0,0 +1,194 @@
+use crate::config::Config;
+use crate::errors::*;
+use std::path::Path;
+use tokio::{fs, process::Command};
+
+pub const SERVICE_NAME: &str = "mts-linkchats-launcher-update.service";
+pub const TIMER_NAME: &str = "mts-linkchats-launcher-update.timer";
+
+/// Quotes an argument of `ExecStart=`, `%` would otherwise start a systemd specifier
+fn quote_exec_arg(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{escaped}\"")
+}
+
+/// Renders the service that runs the `update` subcommand of `exe`
+pub fn render_service(exe: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Update mts-linkchats
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={} update
+",
+        quote_exec_arg(&exe.display().to_string())
+    )
+}
+
+/// Calendar event for checking every `interval` seconds, at night for daily or longer intervals
+fn calendar(interval: usize) -> String {
+    let hours = interval.div_ceil(3600).max(1);
+    if hours < 24 {
+        format!("*-*-* 00/{hours}:00:00")
+    } else {
+        match hours.div_ceil(24) {
+            1 => "*-*-* 03:00:00".to_string(),
+            days => format!("*-*-01/{days} 03:00:00"),
+        }
+    }
+}
+
+/// Renders the timer that starts the service every `interval` seconds.
+///
+/// Checks missed while the computer was off are caught up on the next boot.
+pub fn render_timer(interval: usize) -> String {
+    format!(
+        "[Unit]
+Description=Check for mts-linkchats updates
+
+[Timer]
+OnCalendar={}
+Persistent=true
+RandomizedDelaySec=1h
+
+[Install]
+WantedBy=timers.target
+",
+        calendar(interval)
+    )
+}
+
+async fn systemctl(args: &[&str]) -> Result<()> {
+    debug!("Running systemctl --user {:?}", args);
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .await
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        bail!("systemctl --user {} exited with {}", args.join(" "), status);
+    }
+    Ok(())
+}
+
+/// Writes the service and timer units and enables the timer
+pub async fn install(config: &Config) -> Result<()> {
+    if config.check_update_interval == 0 {
+        bail!("check_update_interval has to be greater than zero for a timer");
+    }
+    let exe = std::env::current_exe().context("Failed to detect path of the launcher")?;
+    let dir = &config.systemd_unit_path;
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| anyhow!("Failed to create {:?}", dir))?;
+
+    for (name, content) in [
+        (SERVICE_NAME, render_service(&exe)),
+        (TIMER_NAME, render_timer(config.check_update_interval)),
+    ] {
+        let path = dir.join(name);
+        info!("Writing {:?}", path);
+        fs::write(&path, content)
+            .await
+            .with_context(|| anyhow!("Failed to write {:?}", path))?;
+    }
+
+    systemctl(&["daemon-reload"]).await?;
+    systemctl(&["enable", "--now", TIMER_NAME]).await?;
+    info!(
+        "Updates are checked by {} ({})",
+        TIMER_NAME,
+        calendar(config.check_update_interval)
+    );
+    Ok(())
+}
+
+/// Disables the timer and removes the units written by [`install`]
+pub async fn uninstall(config: &Config) -> Result<()> {
+    if let Err(err) = systemctl(&["disable", "--now", TIMER_NAME]).await {
+        warn!("Failed to disable {}: {err:#}", TIMER_NAME);
+    }
+
+    for name in [TIMER_NAME, SERVICE_NAME] {
+        let path = config.systemd_unit_path.join(name);
+        match fs::remove_file(&path).await {
+            Ok(()) => info!("Removed {:?}", path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("{:?} does not exist", path)
+            }
+            Err(err) => {
+                return Err(Error::new(err).context(anyhow!("Failed to remove {:?}", path)));
+            }
+        }
+    }
+
+    systemctl(&["daemon-reload"]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_units() {
+        let service = render_service(Path::new("/usr/bin/mts-linkchats-launcher"));
+        assert!(service.contains("ExecStart=\"/usr/bin/mts-linkchats-launcher\" update\n"));
+
+        let timer = render_timer(86400);
+        assert!(timer.contains("OnCalendar=*-*-* 03:00:00\n"));
+        assert!(timer.contains("Persistent=true\n"));
+        assert!(timer.contains("RandomizedDelaySec=1h\n"));
+        assert!(timer.contains("WantedBy=timers.target\n"));
+    }
+
+    #[test]
+    fn test_render_units_complete() {
+        assert_eq!(
+            render_service(Path::new("/usr/bin/mts-linkchats-launcher")),
+            "[Unit]
+Description=Update mts-linkchats
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart=\"/usr/bin/mts-linkchats-launcher\" update
+"
+        );
+        assert_eq!(
+            render_timer(6 * 3600),
+            "[Unit]
+Description=Check for mts-linkchats updates
+
+[Timer]
+OnCalendar=*-*-* 00/6:00:00
+Persistent=true
+RandomizedDelaySec=1h
+
+[Install]
+WantedBy=timers.target
+"
+        );
+    }
+
+    #[test]
+    fn test_render_service_escapes_specifiers() {
+        let service = render_service(Path::new("/opt/100%/\"launcher\""));
+        assert!(service.contains("ExecStart=\"/opt/100%%/\\\"launcher\\\"\" update\n"));
+    }
+
+    #[test]
+    fn test_calendar() {
+        assert_eq!(calendar(600), "*-*-* 00/1:00:00");
+        assert_eq!(calendar(6 * 3600), "*-*-* 00/6:00:00");
+        assert_eq!(calendar(86400), "*-*-* 03:00:00");
+        assert_eq!(calendar(7 * 86400), "*-*-01/7 03:00:00");
+    }
+}
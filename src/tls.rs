@@ -0,0 +1,317 @@
+use crate::config::Config;
+use crate::errors::*;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Returned when a server presents a valid certificate chain that contains none of the pinned keys
+#[derive(Debug)]
+pub struct PinningError {
+    pub host: String,
+}
+
+impl fmt::Display for PinningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Certificate pinning failed: {} did not present any of the pinned public keys",
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for PinningError {}
+
+/// Parses a pinned public key in the `sha256//<base64>` format used by curl
+pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let encoded = pin.strip_prefix("sha256//").unwrap_or(pin);
+    let hash = BASE64
+        .decode(encoded)
+        .with_context(|| anyhow!("Pinned public key {:?} is not valid base64", pin))?;
+    hash.try_into().map_err(|hash: Vec<u8>| {
+        anyhow!(
+            "Pinned public key {:?} has {} bytes, expected a 32 byte sha256 hash",
+            pin,
+            hash.len()
+        )
+    })
+}
+
+pub fn load_ca_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let buf = fs::read(path).with_context(|| anyhow!("Failed to read CA file at {:?}", path))?;
+    let certs = CertificateDer::pem_slice_iter(&buf)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| anyhow!("Failed to parse CA file at {:?}", path))?;
+    if certs.is_empty() {
+        bail!("No certificates found in CA file at {:?}", path);
+    }
+    debug!("Loaded {} certificates from {:?}", certs.len(), path);
+    Ok(certs)
+}
+
+fn spki_sha256(cert: &CertificateDer) -> Option<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    Some(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Host of the last connection that failed the pinning check
+pub type PinFailure = Arc<Mutex<Option<String>>>;
+
+/// Verifies the certificate chain as usual, then requires one of its public keys to be pinned.
+///
+/// Only connections to `hosts` are pinned, e.g. other mirrors or a proxy are verified as usual.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+    hosts: Vec<String>,
+    failure: PinFailure,
+}
+
+impl PinningVerifier {
+    fn new(
+        roots: RootCertStore,
+        pins: Vec<[u8; 32]>,
+        hosts: Vec<String>,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self> {
+        if pins.is_empty() {
+            bail!("No public keys to pin");
+        }
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .context("Failed to setup certificate verification")?;
+        Ok(Self {
+            inner,
+            pins,
+            hosts,
+            failure: PinFailure::default(),
+        })
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let host = server_name.to_str();
+        if !self
+            .hosts
+            .iter()
+            .any(|pinned| pinned.eq_ignore_ascii_case(&host))
+        {
+            debug!("Public keys are not pinned for {}", host);
+            return Ok(verified);
+        }
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_sha256)
+            .any(|hash| self.pins.contains(&hash));
+
+        if pinned {
+            debug!("Certificate of {} matched a pinned public key", host);
+            Ok(verified)
+        } else {
+            let host = host.into_owned();
+            warn!(
+                "Certificate of {} matched none of the pinned public keys",
+                host
+            );
+            *self.failure.lock().unwrap() = Some(host);
+            Err(rustls::Error::General(
+                "certificate public key is not pinned".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Applies extra CA certificates and public key pinning to the http client
+pub fn configure(
+    mut builder: reqwest::ClientBuilder,
+    config: &Config,
+) -> Result<(reqwest::ClientBuilder, Option<PinFailure>)> {
+    let mut extra_roots = Vec::new();
+    for path in &config.ca_certificates {
+        extra_roots.extend(load_ca_certificates(path)?);
+    }
+
+    let pins = config
+        .pinned_public_keys
+        .iter()
+        .map(|pin| parse_pin(pin))
+        .collect::<Result<Vec<_>>>()?;
+
+    if pins.is_empty() {
+        for cert in extra_roots {
+            let cert =
+                reqwest::Certificate::from_der(&cert).context("Failed to load CA certificate")?;
+            builder = builder.add_root_certificate(cert);
+        }
+        return Ok((builder, None));
+    }
+
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for err in native.errors {
+        warn!("Failed to load system certificate: {err:#}");
+    }
+    let (added, ignored) = roots.add_parsable_certificates(native.certs);
+    debug!("Loaded {added} system certificates ({ignored} ignored)");
+    for cert in extra_roots {
+        roots
+            .add(cert)
+            .context("Failed to add CA certificate to the trust store")?;
+    }
+
+    let provider = Arc::new(ring::default_provider());
+    let verifier =
+        PinningVerifier::new(roots, pins, config.pinned_hosts.clone(), provider.clone())?;
+    let failure = verifier.failure.clone();
+
+    let mut tls = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to setup tls")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    debug!(
+        "Pinning {} public keys for {:?}",
+        config.pinned_public_keys.len(),
+        config.pinned_hosts
+    );
+    Ok((builder.use_preconfigured_tls(tls), Some(failure)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pin() -> Result<()> {
+        let hash = parse_pin("sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=")?;
+        assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(b"")));
+        assert_eq!(
+            parse_pin("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=")?,
+            hash
+        );
+        assert!(parse_pin("sha256//AAAA").is_err());
+        assert!(parse_pin("sha256//not base64!").is_err());
+        Ok(())
+    }
+
+    /// A CA and a certificate for `localhost` signed by it
+    fn certificates() -> Result<(CertificateDer<'static>, CertificateDer<'static>)> {
+        let mut params = rcgen::CertificateParams::new(Vec::new())?;
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate()?;
+        let ca = params.self_signed(&ca_key)?;
+        let issuer = rcgen::Issuer::new(params, ca_key);
+
+        let params = rcgen::CertificateParams::new(vec!["localhost".to_string()])?;
+        let leaf = params.signed_by(&rcgen::KeyPair::generate()?, &issuer)?;
+        Ok((ca.der().clone(), leaf.der().clone()))
+    }
+
+    fn verify(verifier: &PinningVerifier, leaf: &CertificateDer, host: &str) -> Result<()> {
+        verifier.verify_server_cert(
+            leaf,
+            &[],
+            &ServerName::try_from(host.to_string())?,
+            &[],
+            UnixTime::now(),
+        )?;
+        Ok(())
+    }
+
+    fn verifier(ca: CertificateDer<'static>, pin: [u8; 32], host: &str) -> Result<PinningVerifier> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca)?;
+        PinningVerifier::new(
+            roots,
+            vec![pin],
+            vec![host.to_string()],
+            Arc::new(ring::default_provider()),
+        )
+    }
+
+    #[test]
+    fn test_pinned_key() -> Result<()> {
+        let (ca, leaf) = certificates()?;
+        let verifier = verifier(ca, spki_sha256(&leaf).unwrap(), "LocalHost")?;
+        verify(&verifier, &leaf, "localhost")?;
+        assert_eq!(*verifier.failure.lock().unwrap(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpinned_key() -> Result<()> {
+        let (ca, leaf) = certificates()?;
+        let pin = <[u8; 32]>::from(Sha256::digest(b"other key"));
+        let pinned = verifier(ca.clone(), pin, "localhost")?;
+        assert!(verify(&pinned, &leaf, "localhost").is_err());
+        assert_eq!(pinned.failure.lock().unwrap().as_deref(), Some("localhost"));
+
+        // hosts without pins only need a valid chain
+        let elsewhere = verifier(ca, pin, "mirror.example.com")?;
+        verify(&elsewhere, &leaf, "localhost")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_pins() {
+        let roots = RootCertStore::empty();
+        let provider = Arc::new(ring::default_provider());
+        assert!(PinningVerifier::new(roots, Vec::new(), Vec::new(), provider).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::errors::*;
+
+const UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("k", 1 << 10),
+    ("kb", 1000),
+    ("kib", 1 << 10),
+    ("m", 1 << 20),
+    ("mb", 1000 * 1000),
+    ("mib", 1 << 20),
+    ("g", 1 << 30),
+    ("gb", 1000 * 1000 * 1000),
+    ("gib", 1 << 30),
+];
+
+/// Parses a human readable size like `512KiB`, `1.5 MB` or `100` (bytes).
+///
+/// `K`, `M` and `G` without a suffix are binary units.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number
+        .parse::<f64>()
+        .with_context(|| anyhow!("Invalid size {:?}", value))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier = if unit.is_empty() {
+        1
+    } else {
+        UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, multiplier)| *multiplier)
+            .with_context(|| anyhow!("Unknown size unit {:?} in {:?}", unit, value))?
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a transfer rate like `2MiB/s`, returning bytes per second
+pub fn parse_rate(value: &str) -> Result<u64> {
+    let size = value.trim();
+    let size = size
+        .strip_suffix("/s")
+        .or_else(|| size.strip_suffix("ps"))
+        .unwrap_or(size);
+    let rate = parse_size(size)?;
+    if rate == 0 {
+        bail!("Transfer rate {:?} must be greater than zero", value);
+    }
+    Ok(rate)
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 || unit == "GiB" {
+            return if unit == "B" {
+                format!("{bytes} B")
+            } else {
+                format!("{size:.1} {unit}")
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("100")?, 100);
+        assert_eq!(parse_size("512KiB")?, 512 * 1024);
+        assert_eq!(parse_size("1.5 MB")?, 1_500_000);
+        assert_eq!(parse_size("2M")?, 2 * 1024 * 1024);
+        assert_eq!(parse_size("1gib")?, 1 << 30);
+        assert!(parse_size("MiB").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rate() -> Result<()> {
+        assert_eq!(parse_rate("2MiB/s")?, 2 * 1024 * 1024);
+        assert_eq!(parse_rate("500KBps")?, 500_000);
+        assert_eq!(parse_rate("1024")?, 1024);
+        assert!(parse_rate("0/s").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(100), "100 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(2 * 1024 * 1024), "2.0 MiB");
+        assert_eq!(format_size(3 << 40), "3072.0 GiB");
+    }
+}
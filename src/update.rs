@@ -0,0 +1,700 @@
+use crate::apt::Client;
+use crate::asar::{self, PackageJson};
+use crate::cache::ArchiveCache;
+use crate::config::{BIN_APP_NAME, Config};
+use crate::errors::*;
+use crate::extract::{self, Unpacker};
+use crate::offline;
+use crate::pkg::VersionDetector;
+use crate::signature;
+use crate::state::{RejectedUpdate, StagedUpdate, State, StateFile};
+use crate::verify;
+use crate::version::Version;
+use std::time::{Duration, SystemTime};
+use tokio::{fs, time};
+
+/// How often to look for the app again before a staged update is kept for later
+const EXIT_CHECKS: u32 = 5;
+/// Delay before the first check is repeated, doubled for every further check
+const EXIT_CHECK_DELAY: Duration = Duration::from_millis(100);
+
+pub async fn should_update(config: &Config, state: &State) -> Result<bool> {
+    if config.force_check_update {
+        Ok(true)
+    } else if !config.check_update {
+        Ok(false)
+    } else {
+        // staging an update in the background does not touch the running installation
+        if state.get_pid().is_some() && !config.background_update {
+            return Ok(false);
+        }
+
+        let Ok(since_update) = SystemTime::now().duration_since(state.last_update_check) else {
+            // if the last update time is somehow in the future, check for updates now
+            return Ok(true);
+        };
+
+        let hours_since = since_update.as_secs() / 3600;
+        let days_since = hours_since / 24;
+        let hours_since = hours_since % 24;
+
+        debug!(
+            "Last update check was {} days and {} hours ago",
+            days_since, hours_since
+        );
+        let interval: u64 = config.check_update_interval.try_into()?;
+        Ok(since_update >= Duration::from_secs(interval))
+    }
+}
+
+/// Checks that the installed app reports the version in the state file
+pub fn check_installed(state: &State, package: &PackageJson) -> Result<()> {
+    let Some(version) = &state.version else {
+        return Ok(());
+    };
+    let reported = package
+        .version
+        .parse::<Version>()
+        .context("Installed app reports an invalid version")?;
+    if !reported.same_release(version) {
+        bail!(
+            "Installed app reports version {} instead of {}",
+            reported,
+            version
+        );
+    }
+    Ok(())
+}
+
+/// Reads the metadata of the installed app, logging a warning if it does not match the state
+async fn verify_installed(config: &Config, state: &State) {
+    match asar::installed_package(&config.install_path).await {
+        Ok(Some(package)) => match check_installed(state, &package) {
+            Ok(()) => debug!("Installed app reports version {}", package.version),
+            Err(err) => warn!("{err:#}"),
+        },
+        Ok(None) => debug!(
+            "Installed app has no {}, skipping version check",
+            asar::APP_ASAR
+        ),
+        Err(err) => warn!("{err:#}"),
+    }
+}
+
+/// Returns why `version` must not be installed, if it must not
+fn refusal(config: &Config, state: &State, version: &Version) -> Option<String> {
+    if let Some(pinned) = &config.pin_version
+        && version != pinned
+    {
+        return Some(format!(
+            "Refusing to install version {}, only the pinned version {} can be installed",
+            version, pinned
+        ));
+    }
+
+    // a stale mirror must not take users back to an older version, going back to a pinned
+    // version is the whole point of pinning it though
+    let staged_version = state.staged.as_ref().map(|staged| &staged.version);
+    let current = state.version.as_ref().max(staged_version)?;
+    if version < current && !config.allow_downgrade && config.pin_version.is_none() {
+        return Some(format!(
+            "Refusing to downgrade from version {} to {}, use --allow-downgrade to install it anyway",
+            current, version
+        ));
+    }
+    None
+}
+
+/// Installs the latest archive, or with `stage` extracts it next to the installation to be
+/// applied on the next launch
+pub async fn update(config: &Config, state_file: &mut StateFile, stage: bool) -> Result<()> {
+    // neither needs the network, an archive passed with --tar is still checked against the pin
+    if config.tar_path.is_none()
+        && let Some(installed) = &state_file.state.version
+        && (config.hold || config.pin_version.as_ref() == Some(installed))
+    {
+        if config.hold {
+            info!("Updates are held, keeping version {}", installed);
+        } else {
+            info!("Pinned version {} is already installed, skip...", installed);
+        }
+        state_file.state.last_update_check = SystemTime::now();
+        state_file.save().await?;
+        return Ok(());
+    }
+
+    if config.hold && state_file.state.version.is_none() {
+        info!("Updates are held, but nothing is installed yet, installing the latest version");
+    }
+
+    // a pinned version may still be in the archive cache, no need to download it again
+    let local = if config.tar_path.is_none() && (config.offline || config.pin_version.is_some()) {
+        offline::find_archive(config, state_file.state.version.as_ref()).await?
+    } else {
+        None
+    };
+
+    let (tar_path, sha256, signatures, remote, unpacked) = if let Some(tar_path) = &config.tar_path
+    {
+        let sha256 = if config.sha256_sidecar {
+            verify::local_sidecar(tar_path).await?
+        } else {
+            None
+        };
+        let signatures = signature::local(config, tar_path).await?;
+        (tar_path.clone(), sha256, signatures, None, None)
+    } else if let Some((tar_path, version)) = local {
+        info!("Installing version {:?} from {:?}", version, tar_path);
+
+        let sha256 = if config.sha256_sidecar {
+            verify::local_sidecar(&tar_path).await?
+        } else {
+            None
+        };
+        let signatures = signature::local(config, &tar_path).await?;
+        (tar_path, sha256, signatures, None, None)
+    } else if config.offline {
+        info!("No newer archive is available offline, skip...");
+        state_file.state.last_update_check = SystemTime::now();
+        state_file.save().await?;
+        return Ok(());
+    } else {
+        let mut client = Client::new(config)?;
+        if stage {
+            client = client.without_progress();
+        }
+
+        let state = &mut state_file.state;
+        // a staged update is newer than the installed one
+        let installed = match &state.staged {
+            Some(staged) => staged.remote.as_ref(),
+            None => state.remote.as_ref().filter(|_| state.version.is_some()),
+        };
+        // an archive that was refused before is only downloaded again once it changed, unless
+        // the configuration allows installing it now
+        let rejected = state
+            .rejected
+            .as_ref()
+            .filter(|rejected| refusal(config, state, &rejected.version).is_some())
+            .map(|rejected| &rejected.remote);
+        let Some(downloaded) = client.download_tar(rejected.or(installed)).await? else {
+            info!("Remote archive was not modified since the last update, skip...");
+            state.last_update_check = SystemTime::now();
+            state_file.save().await?;
+            return Ok(());
+        };
+        (
+            downloaded.path,
+            downloaded.sha256,
+            downloaded.signatures,
+            Some(downloaded.validators),
+            downloaded.unpacked,
+        )
+    };
+
+    // nothing in an archive is read before it is verified, unless it was unpacked while it was
+    // downloaded because there is nothing to verify
+    let verified = async {
+        let actual = match &unpacked {
+            Some(unpacked) => unpacked.sha256.clone(),
+            None => verify::sha256_file(&tar_path).await?,
+        };
+        verify::archive(config, &actual, sha256.as_deref()).await?;
+        signature::verify(config, &tar_path, &signatures).await
+    }
+    .await;
+    if let Err(err) = verified {
+        if remote.is_some() {
+            fs::remove_file(&tar_path).await.ok();
+        }
+        return Err(err.context("Archive verification failed, keeping the current installation"));
+    }
+
+    let unpacked = match unpacked {
+        Some(unpacked) => unpacked,
+        None => {
+            let detector = VersionDetector::new(config);
+            match Unpacker::unpack_file(&tar_path, &config.cache_path, &detector).await {
+                Ok(unpacked) => unpacked,
+                Err(err) => {
+                    if remote.is_some() {
+                        fs::remove_file(&tar_path).await.ok();
+                    }
+                    return Err(err.context("Failed to unpack archive"));
+                }
+            }
+        }
+    };
+
+    let version = unpacked.version.clone();
+    let state = &mut state_file.state;
+
+    state.last_update_check = SystemTime::now();
+
+    if let Some(reason) = refusal(config, state, &version) {
+        warn!("{}", reason);
+        if let Some(remote) = &remote {
+            fs::remove_file(&tar_path).await.ok();
+            state.rejected = Some(RejectedUpdate {
+                version,
+                remote: remote.clone(),
+            });
+        }
+        state_file.save().await?;
+        return Ok(());
+    }
+    state.rejected = None;
+
+    let staged_version = state.staged.as_ref().map(|staged| &staged.version);
+    if let Some(current) = state.version.as_ref().max(staged_version)
+        && version < *current
+    {
+        warn!("Downgrading from version {} to {}", current, version);
+    }
+
+    if stage {
+        if state.version.as_ref() == Some(&version) || staged_version == Some(&version) {
+            info!("Latest version is already installed or staged, skip...");
+        } else {
+            info!(
+                "Staging version {:?}, it is applied once the app exits",
+                version
+            );
+            extract::stage(unpacked, config).await?;
+            state.staged = Some(StagedUpdate {
+                version: version.clone(),
+                remote: remote.clone(),
+            });
+        }
+    } else {
+        if state.version.as_ref() != Some(&version) {
+            info!("Updating to version {}...", version);
+            extract::pkg(unpacked, config).await?;
+            state.version = Some(version.clone());
+        } else if config.force_check_update {
+            info!(
+                "Latest version is already installed, but --tar options is passed. Force update..."
+            );
+            extract::pkg(unpacked, config).await?;
+        } else {
+            info!("Latest version is already installed, skip...");
+        }
+        state.remote = remote.clone();
+        // whatever was staged is older than what was just installed
+        state.staged = None;
+        verify_installed(config, state).await;
+    }
+
+    let cache = ArchiveCache::new(config);
+    if cache.is_enabled()
+        && let Err(err) = cache.store(&tar_path, &version, remote.is_some()).await
+    {
+        warn!("Failed to keep archive in cache: {err:#}");
+    }
+    if remote.is_some() && fs::try_exists(&tar_path).await.unwrap_or(false) {
+        debug!("Removing downloaded archive {:?}", tar_path);
+        if let Err(err) = fs::remove_file(&tar_path).await {
+            warn!("Failed to remove downloaded archive: {err:#}");
+        }
+    }
+    state_file.save().await?;
+
+    Ok(())
+}
+
+/// Moves a staged update in place, if there is one
+pub async fn apply_staged(config: &Config, state_file: &mut StateFile) -> Result<()> {
+    let state = &mut state_file.state;
+    let Some(staged) = state.staged.take() else {
+        return Ok(());
+    };
+
+    if config.hold {
+        info!(
+            "Updates are held, keeping the staged update to version {} for later",
+            staged.version
+        );
+        state.staged = Some(staged);
+        return Ok(());
+    }
+    if let Some(pinned) = &config.pin_version
+        && staged.version != *pinned
+    {
+        warn!(
+            "Discarding staged update to version {}, version {} is pinned",
+            staged.version, pinned
+        );
+        extract::discard_staged(config).await?;
+        return state_file.save().await;
+    }
+
+    if fs::try_exists(&config.staged_path).await.unwrap_or(false) {
+        info!("Applying staged update to version {:?}...", staged.version);
+        extract::apply_staged(config).await?;
+        state.version = Some(staged.version);
+        state.remote = staged.remote;
+        verify_installed(config, state).await;
+    } else {
+        warn!(
+            "Staged update is missing from {:?}, discarding it",
+            config.staged_path
+        );
+    }
+    state_file.save().await
+}
+
+/// Checks for updates and installs them without launching the app, for scheduled updates
+pub async fn update_now(config: &Config) -> Result<()> {
+    let mut state_file = StateFile::load(&config.state_path).await?;
+
+    // stage first, the app may be running and is only updated once it exits then
+    update(config, &mut state_file, true).await?;
+    if state_file.state.get_pid().is_none() {
+        apply_staged(config, &mut state_file).await?;
+    } else if state_file.state.staged.is_some() {
+        info!(
+            "`{}` is running, the update is applied once it exits",
+            BIN_APP_NAME
+        );
+    }
+    Ok(())
+}
+
+/// Loads the state file once the app has exited, `None` if nothing is staged or the app is
+/// still running.
+///
+/// Helper processes of the app may outlive the process that was waited for by a moment, they are
+/// given a short while to exit before the app is considered to be still running.
+async fn staged_after_exit(config: &Config) -> Result<Option<StateFile>> {
+    let mut delay = EXIT_CHECK_DELAY;
+    for check in 1..=EXIT_CHECKS {
+        // the update may have been staged by another launcher, the file has the latest state
+        let state_file = StateFile::load(&config.state_path).await?;
+        if state_file.state.staged.is_none() {
+            return Ok(None);
+        }
+        if state_file.state.get_pid().is_none() {
+            return Ok(Some(state_file));
+        }
+        if check < EXIT_CHECKS {
+            debug!(
+                "`{}` is still running, checking again in {}ms",
+                BIN_APP_NAME,
+                delay.as_millis()
+            );
+            time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    debug!("Another instance is still running, keeping the staged update for later");
+    Ok(None)
+}
+
+/// Applies an update that was staged while the app was running.
+///
+/// With `relaunch_after_update` the updated version is started by `relaunch`, which returns true
+/// if the app was supervised until it exited on its own.
+pub async fn apply_after_exit(
+    config: &Config,
+    mut relaunch: impl AsyncFnMut() -> Result<bool>,
+) -> Result<()> {
+    loop {
+        let Some(mut state_file) = staged_after_exit(config).await? else {
+            return Ok(());
+        };
+
+        apply_staged(config, &mut state_file).await?;
+        if !config.relaunch_after_update {
+            return Ok(());
+        }
+
+        info!("Relaunching the updated version...");
+        if !relaunch().await? {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_in;
+    use crate::http::{
+        Validators,
+        tests::{request_header, response, serve},
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::UNIX_EPOCH,
+    };
+
+    /// Serves the archive of `version` at `/linkchats-desktop.tar`, recording the requests
+    async fn serve_archive(version: &str) -> Result<(String, Arc<Mutex<Vec<String>>>)> {
+        let path = format!("linkchats-desktop-{version}/mtslink.bin");
+        let archive = crate::pkg::tests::tar(&[(path.as_str(), b"")])?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            response("200 OK", &[("ETag", "\"v1\"")], &archive)
+        })
+        .await?;
+        Ok((format!("{url}/linkchats-desktop.tar"), requests))
+    }
+
+    /// A state file with `version` installed
+    async fn installed(config: &Config, version: Option<&str>) -> Result<StateFile> {
+        let mut state_file = StateFile::load(&config.state_path).await?;
+        state_file.state.version = version.map(str::parse).transpose()?;
+        Ok(state_file)
+    }
+
+    #[tokio::test]
+    async fn test_update_not_modified() -> Result<()> {
+        const LAST_MODIFIED: &str = "Mon, 01 Jan 2024 00:00:00 GMT";
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            response("304 Not Modified", &[("ETag", "\"v1\"")], b"")
+        })
+        .await?;
+
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![format!("{url}/linkchats-desktop.tar.gz")];
+        let remote = Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some(LAST_MODIFIED.to_string()),
+            content_length: Some(1024),
+        };
+        let mut state_file = StateFile::load(&config.state_path).await?;
+        state_file.state.version = Some("1.2.3".parse()?);
+        state_file.state.remote = Some(remote.clone());
+
+        // checking in the background avoids the progress window
+        update(&config, &mut state_file, true).await?;
+
+        // only the time of the check changed
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+        assert_eq!(state.remote, Some(remote));
+        assert_eq!(state.staged, None);
+        assert!(state.last_update_check > UNIX_EPOCH);
+        assert!(!config.install_path.exists());
+
+        // the conditions are the validators of the installed archive
+        let requests = requests.lock().unwrap();
+        assert!(!requests.is_empty());
+        for request in requests.iter() {
+            assert_eq!(request_header(request, "if-none-match"), Some("\"v1\""));
+            assert_eq!(
+                request_header(request, "if-modified-since"),
+                Some(LAST_MODIFIED)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_hold() -> Result<()> {
+        let (url, requests) = serve_archive("1.2.4").await?;
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![url];
+        config.hold = true;
+        let mut state_file = installed(&config, Some("1.2.3")).await?;
+
+        update(&config, &mut state_file, true).await?;
+
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+        assert_eq!(state.staged, None);
+        assert!(state.last_update_check > UNIX_EPOCH);
+        assert!(requests.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_hold_nothing_installed() -> Result<()> {
+        let (url, _) = serve_archive("1.2.4").await?;
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![url];
+        config.hold = true;
+        let mut state_file = installed(&config, None).await?;
+
+        // there is nothing to keep, the latest version is taken
+        update(&config, &mut state_file, true).await?;
+
+        let state = StateFile::load(&config.state_path).await?.state;
+        let staged = state.staged.unwrap();
+        assert_eq!(staged.version, "1.2.4".parse()?);
+        assert!(config.staged_path.join("mtslink.bin").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_pin() -> Result<()> {
+        let (url, _) = serve_archive("1.2.4").await?;
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![url];
+        config.pin_version = Some("1.2.2".parse()?);
+        let mut state_file = installed(&config, Some("1.2.3")).await?;
+
+        update(&config, &mut state_file, true).await?;
+
+        // the newer version is refused, and remembered so it is not downloaded again
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+        assert_eq!(state.staged, None);
+        let rejected = state.rejected.unwrap();
+        assert_eq!(rejected.version, "1.2.4".parse()?);
+        assert_eq!(rejected.remote.etag.as_deref(), Some("\"v1\""));
+        assert!(!config.staged_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_staged_pin() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.pin_version = Some("1.2.3".parse()?);
+        std::fs::create_dir_all(&config.staged_path)?;
+        std::fs::write(config.staged_path.join("mtslink.bin"), b"")?;
+        let mut state_file = installed(&config, Some("1.2.3")).await?;
+        state_file.state.staged = Some(StagedUpdate {
+            version: "1.2.4".parse()?,
+            remote: None,
+        });
+
+        apply_staged(&config, &mut state_file).await?;
+
+        // the staged update is gone, from the state file and from disk
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+        assert_eq!(state.staged, None);
+        assert!(!config.staged_path.exists());
+        assert!(!config.install_path.exists());
+        Ok(())
+    }
+
+    /// Leaves an update to `version` behind as if it was staged in the background
+    async fn stage(config: &Config, version: &str) -> Result<()> {
+        std::fs::create_dir_all(&config.staged_path)?;
+        std::fs::write(config.staged_path.join("mtslink.bin"), version)?;
+        let mut state_file = StateFile::load(&config.state_path).await?;
+        state_file.state.staged = Some(StagedUpdate {
+            version: version.parse()?,
+            remote: None,
+        });
+        state_file.save().await
+    }
+
+    #[tokio::test]
+    async fn test_apply_after_exit_relaunch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.relaunch_after_update = true;
+        std::fs::create_dir_all(&config.install_path)?;
+        std::fs::write(config.install_path.join("mtslink.bin"), "1.2.3")?;
+        installed(&config, Some("1.2.3")).await?.save().await?;
+        stage(&config, "1.2.4").await?;
+
+        let mut launched = Vec::new();
+        apply_after_exit(&config, async || {
+            let state = StateFile::load(&config.state_path).await?.state;
+            launched.push(std::fs::read_to_string(
+                config.install_path.join("mtslink.bin"),
+            )?);
+            assert_eq!(state.staged, None);
+            // the relaunched app runs until another update is staged in the background
+            if state.version == Some("1.2.4".parse()?) {
+                stage(&config, "1.2.5").await?;
+            }
+            Ok(true)
+        })
+        .await?;
+
+        // every staged update was applied before the app was started again
+        assert_eq!(launched, ["1.2.4", "1.2.5"]);
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.5".parse()?));
+        assert_eq!(state.staged, None);
+        assert!(!config.staged_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_after_exit_waits_for_helpers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = config_in(dir.path())?;
+        installed(&config, Some("1.2.3")).await?.save().await?;
+        stage(&config, "1.2.4").await?;
+
+        // a helper process of the app that exits shortly after the app itself
+        let helper = dir.path().join(BIN_APP_NAME);
+        std::fs::copy("/bin/sleep", &helper)?;
+        let mut helper = tokio::process::Command::new(helper).arg("0.3").spawn()?;
+        let helper = tokio::spawn(async move { helper.wait().await });
+
+        apply_after_exit(&config, async || Ok(false)).await?;
+        helper.await??;
+
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.4".parse()?));
+        assert_eq!(state.staged, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_stage() -> Result<()> {
+        let (url, _) = serve_archive("1.2.4").await?;
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![url];
+        std::fs::create_dir_all(&config.install_path)?;
+        std::fs::write(config.install_path.join("mtslink.bin"), "1.2.3")?;
+        let mut state_file = installed(&config, Some("1.2.3")).await?;
+
+        update(&config, &mut state_file, true).await?;
+
+        // the running installation is left alone, the update waits next to it
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.3".parse()?));
+        let staged = state.staged.unwrap();
+        assert_eq!(staged.version, "1.2.4".parse()?);
+        assert_eq!(staged.remote.unwrap().etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            std::fs::read_to_string(config.install_path.join("mtslink.bin"))?,
+            "1.2.3"
+        );
+        assert!(config.staged_path.join("mtslink.bin").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_stage_then_apply() -> Result<()> {
+        let (url, _) = serve_archive("1.2.4").await?;
+        let dir = tempfile::tempdir()?;
+        let mut config = config_in(dir.path())?;
+        config.sources = vec![url];
+        std::fs::create_dir_all(&config.install_path)?;
+        std::fs::write(config.install_path.join("mtslink.bin"), "1.2.3")?;
+        let mut state_file = installed(&config, Some("1.2.3")).await?;
+
+        // staged while the app was running, applied once it exited
+        update(&config, &mut state_file, true).await?;
+        let remote = state_file.state.staged.as_ref().unwrap().remote.clone();
+        apply_after_exit(&config, async || Ok(false)).await?;
+
+        let state = StateFile::load(&config.state_path).await?.state;
+        assert_eq!(state.version, Some("1.2.4".parse()?));
+        assert_eq!(state.remote, remote);
+        assert_eq!(state.staged, None);
+        assert_eq!(std::fs::read(config.install_path.join("mtslink.bin"))?, b"");
+        assert!(!config.staged_path.exists());
+        Ok(())
+    }
+}
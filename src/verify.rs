@@ -0,0 +1,160 @@
+use crate::config::Config;
+use crate::errors::*;
+use sha2::{Digest, Sha256};
+use std::{fs::File, io, path::Path};
+use tokio::{fs, task};
+
+/// Computes the hex encoded sha256 hash of a file
+pub async fn sha256_file(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || {
+        let mut file = File::open(&path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// Returns true if the archive has to match a checksum
+pub fn is_enabled(config: &Config) -> bool {
+    config.sha256.is_some() || config.sha256_sidecar || config.checksum_file.is_some()
+}
+
+fn normalize_hash(hash: &str) -> Result<String> {
+    let hash = hash.trim().to_ascii_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid sha256 hash {:?}", hash);
+    }
+    Ok(hash)
+}
+
+/// Parses a `.sha256` sidecar file, containing either just the hash or `<hash>  <filename>`
+pub fn parse_sidecar(text: &str) -> Result<String> {
+    let hash = text
+        .split_whitespace()
+        .next()
+        .context("Checksum file is empty")?;
+    normalize_hash(hash)
+}
+
+/// Parses a hash list in the format of `sha256sum`, returning the hashes it contains
+pub fn parse_list(text: &str) -> Result<Vec<String>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| normalize_hash(line.split_whitespace().next().unwrap_or_default()))
+        .collect()
+}
+
+/// Reads the `.sha256` file next to a local archive, if there is one
+pub async fn local_sidecar(path: &Path) -> Result<Option<String>> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    match fs::read_to_string(&sidecar).await {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => {
+            Err(Error::new(err).context(anyhow!("Failed to read checksum file {:?}", sidecar)))
+        }
+    }
+}
+
+/// Verifies the archive against every configured checksum source.
+///
+/// `actual` is the sha256 hash of the archive, `sidecar` the content of the `.sha256` file
+/// published next to it, if any.
+pub async fn archive(config: &Config, actual: &str, sidecar: Option<&str>) -> Result<()> {
+    if config.sha256.is_none() && sidecar.is_none() && config.checksum_file.is_none() {
+        if config.sha256_sidecar {
+            bail!("Checksum verification is enabled, but no .sha256 file was found");
+        }
+        debug!("No checksums configured, skipping verification");
+        return Ok(());
+    }
+
+    debug!("Archive sha256: {}", actual);
+
+    if let Some(expected) = &config.sha256 {
+        if normalize_hash(expected)? != actual {
+            bail!(
+                "Checksum mismatch: expected sha256 {}, archive has {}",
+                expected,
+                actual
+            );
+        }
+        info!("Archive matches the pinned sha256 hash");
+    }
+
+    if let Some(sidecar) = sidecar {
+        let expected = parse_sidecar(sidecar).context("Failed to parse .sha256 file")?;
+        if expected != actual {
+            bail!(
+                "Checksum mismatch: .sha256 file has {}, archive has {}",
+                expected,
+                actual
+            );
+        }
+        info!("Archive matches its .sha256 file");
+    } else if config.sha256_sidecar {
+        bail!("Checksum verification is enabled, but no .sha256 file was found");
+    }
+
+    if let Some(list_path) = &config.checksum_file {
+        let text = fs::read_to_string(list_path)
+            .await
+            .with_context(|| anyhow!("Failed to read checksum file {:?}", list_path))?;
+        let hashes = parse_list(&text)
+            .with_context(|| anyhow!("Failed to parse checksum file {:?}", list_path))?;
+        if !hashes.iter().any(|hash| hash == actual) {
+            bail!(
+                "Checksum mismatch: sha256 {} is not listed in {:?}",
+                actual,
+                list_path
+            );
+        }
+        info!("Archive is listed in {:?}", list_path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parse_sidecar() -> Result<()> {
+        assert_eq!(parse_sidecar(EMPTY_SHA256)?, EMPTY_SHA256);
+        assert_eq!(
+            parse_sidecar(&format!(
+                "{}  linkchats-desktop.tar.gz\n",
+                EMPTY_SHA256.to_uppercase()
+            ))?,
+            EMPTY_SHA256
+        );
+        assert!(parse_sidecar("").is_err());
+        assert!(parse_sidecar("abc  linkchats-desktop.tar.gz").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_list() -> Result<()> {
+        let hashes = parse_list(&format!(
+            "# approved builds\n{EMPTY_SHA256}  linkchats-1.0.0.tar.gz\n\n{}  linkchats-1.1.0.tar.gz\n",
+            "a".repeat(64)
+        ))?;
+        assert_eq!(hashes, [EMPTY_SHA256.to_string(), "a".repeat(64)]);
+        assert!(parse_list("nothex  linkchats.tar.gz").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sha256_file() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        assert_eq!(sha256_file(file.path()).await?, EMPTY_SHA256);
+        Ok(())
+    }
+}
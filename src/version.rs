@@ -0,0 +1,312 @@
+use crate::deb;
+use crate::errors::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// Version of the app, `[epoch:]major.minor.patch[-pre.release][+build]`.
+///
+/// Ordered like a semantic version: a pre-release sorts before its release and build metadata is
+/// ignored. Missing release components count as zero, so `1.2` equals `1.2.0`.
+///
+/// Versions of `.deb` packages and versions containing `~` are Debian versions, they are compared
+/// like dpkg does: `~` sorts before everything and the revision after the last `-` sorts after the
+/// upstream version, so `1.2.3~rc1-1 < 1.2.3 < 1.2.3-1`.
+#[derive(Clone)]
+pub struct Version {
+    epoch: u64,
+    release: Vec<u64>,
+    pre: Vec<Identifier>,
+    build: Option<String>,
+    /// The whole version, if it is a Debian version
+    debian: Option<String>,
+}
+
+/// Part of a pre-release, numeric identifiers sort before alphanumeric ones
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // versions taken from archive paths may end with a slash
+        let version = s.trim().trim_end_matches('/');
+        let version = version.strip_prefix('v').unwrap_or(version);
+
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (
+                epoch
+                    .parse()
+                    .with_context(|| anyhow!("Invalid epoch in version {:?}", s))?,
+                rest,
+            ),
+            None => (0, version),
+        };
+        let (rest, build) = match rest.split_once('+') {
+            Some((rest, build)) if !build.is_empty() => (rest, Some(build.to_string())),
+            Some(_) => bail!("Empty build metadata in version {:?}", s),
+            None => (rest, None),
+        };
+        let (release, pre) = match rest.find(['-', '~']) {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let release = release
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .with_context(|| anyhow!("Invalid version {:?}", s))?;
+        let pre = pre
+            .map(|pre| {
+                pre.split('.')
+                    .map(|identifier| {
+                        if identifier.is_empty() {
+                            bail!("Empty pre-release identifier in version {:?}", s);
+                        }
+                        Ok(match identifier.parse() {
+                            Ok(n) => Identifier::Numeric(n),
+                            Err(_) => Identifier::Alphanumeric(identifier.to_string()),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Version {
+            epoch,
+            release,
+            pre,
+            build,
+            // semantic versions have no `~`, it only shows up in Debian versions
+            debian: version.contains('~').then(|| version.to_string()),
+        })
+    }
+}
+
+impl Version {
+    /// Parses the version of a Debian package, e.g. from its control file
+    pub fn from_debian(s: &str) -> Result<Self> {
+        let mut version = s.parse::<Version>()?;
+        version.debian = Some(s.trim().to_string());
+        Ok(version)
+    }
+
+    /// Whether both have the same release numbers, ignoring epoch, pre-release and build.
+    ///
+    /// An app reports its upstream version, without the revision of the package it came in.
+    pub fn same_release(&self, other: &Version) -> bool {
+        let len = self.release.len().max(other.release.len());
+        let release = |version: &Version, i: usize| version.release.get(i).copied().unwrap_or(0);
+        (0..len).all(|i| release(self, i) == release(other, i))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(debian) = &self.debian {
+            return f.write_str(debian);
+        }
+        if self.epoch > 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        for (i, n) in self.release.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{n}")?;
+        }
+        for (i, identifier) in self.pre.iter().enumerate() {
+            f.write_str(if i > 0 { "." } else { "-" })?;
+            match identifier {
+                Identifier::Numeric(n) => write!(f, "{n}")?,
+                Identifier::Alphanumeric(s) => f.write_str(s)?,
+            }
+        }
+        if let Some(build) = &self.build {
+            write!(f, "+{build}")?;
+        }
+        Ok(())
+    }
+}
+
+// shown like the string it was parsed from, it ends up in a lot of log messages
+impl fmt::Debug for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // a semantic version is the upstream version of a package without a revision
+        if self.debian.is_some() || other.debian.is_some() {
+            return deb::compare_versions(&self.to_string(), &other.to_string());
+        }
+
+        let len = self.release.len().max(other.release.len());
+        let release = |version: &Version, i: usize| version.release.get(i).copied().unwrap_or(0);
+        let pre = match (self.pre.is_empty(), other.pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.pre.cmp(&other.pre),
+        };
+
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| {
+                (0..len)
+                    .map(|i| release(self, i).cmp(&release(other, i)))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then(pre)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+/// Marks a saved Debian version, `1.2.3-1` alone would be read back as a semantic version
+const DEBIAN_TAG: &str = "debian:";
+
+impl Version {
+    /// Parses a version written by [`Serialize`], which keeps Debian versions apart
+    pub fn from_saved(s: &str) -> Result<Self> {
+        match s.strip_prefix(DEBIAN_TAG) {
+            Some(debian) => Version::from_debian(debian),
+            None => s.parse(),
+        }
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.debian {
+            Some(debian) => serializer.collect_str(&format_args!("{DEBIAN_TAG}{debian}")),
+            None => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        Version::from_saved(&version).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(version: &str) -> Version {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(v("1.2.3/").to_string(), "1.2.3");
+        assert_eq!(v("v1.2.3").to_string(), "1.2.3");
+        assert_eq!(v("1:1.2.3-1").to_string(), "1:1.2.3-1");
+        assert_eq!(v("2.0.0-rc.1+build.5").to_string(), "2.0.0-rc.1+build.5");
+        assert_eq!(v("1.0~beta1").to_string(), "1.0~beta1");
+        assert_eq!(
+            Version::from_debian("1:1.2.3~rc1-1")?.to_string(),
+            "1:1.2.3~rc1-1"
+        );
+
+        assert!("".parse::<Version>().is_err());
+        assert!("latest".parse::<Version>().is_err());
+        assert!("1.2.x".parse::<Version>().is_err());
+        assert!("1.2.3-".parse::<Version>().is_err());
+        assert!("1.2.3+".parse::<Version>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(v("1.10.0") > v("1.9.2"));
+        assert_eq!(v("1.2.3/"), v("1.2.3"));
+        assert!(v("1.2.3") < v("1.2.4/"));
+        assert!(v("2.0.0") > v("1.99.99"));
+        assert_eq!(v("1.2"), v("1.2.0"));
+        assert!(v("1:0.9.0") > v("2.0.0"));
+        assert_eq!(v("1.2.3+build.1"), v("1.2.3+build.2"));
+
+        assert!(v("1:1.10.0-1").same_release(&v("1.10")));
+        assert!(!v("1.10.0").same_release(&v("1.1.0")));
+
+        // precedence example of the semver specification
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_save_debian() -> Result<()> {
+        #[derive(Serialize, Deserialize)]
+        struct Saved {
+            installed: Version,
+            staged: Version,
+        }
+
+        let saved = toml::to_string(&Saved {
+            installed: Version::from_debian("1.2.3-1")?,
+            staged: v("1.2.3"),
+        })?;
+        assert!(saved.contains("installed = \"debian:1.2.3-1\""));
+        assert!(saved.contains("staged = \"1.2.3\""));
+
+        let saved = toml::from_str::<Saved>(&saved)?;
+        assert_eq!(saved.installed.to_string(), "1.2.3-1");
+        assert!(saved.installed > saved.staged);
+        assert_eq!(saved.installed.clone().max(saved.staged), saved.installed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_debian() -> Result<()> {
+        let deb = Version::from_debian;
+        assert!(deb("1.2.3~rc1-1")? < deb("1.2.3-1")?);
+        assert!(deb("1.2.3-1")? < deb("1.2.3-2")?);
+        assert!(deb("1.2.3-2")? < deb("1.2.4-1")?);
+        assert!(v("1.2.3") < deb("1.2.3-1")?);
+        assert!(deb("1.2.3-1")? > v("1.2.3"));
+        assert!(v("1.2.3~rc1") < v("1.2.3"));
+        assert_eq!(deb("1.2.3")?, v("1.2.3"));
+
+        // Debian versions are stored as text, e.g. in the state file
+        let version = deb("1.2.3~rc1-1")?;
+        assert_eq!(v(&version.to_string()), version);
+        let version = deb("1.2.3-1")?;
+        assert_eq!(v(&version.to_string()), version);
+        Ok(())
+    }
+}